@@ -0,0 +1,61 @@
+//! Times hits, misses and removals against a clock replacer with a
+//! large store. Run with `cargo bench --bench clock_replacer`.
+
+use composter::buffer_pool_manager::ReplacerNode;
+use composter::clock_replacer::Replacer;
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+const OPS: usize = 1_000_000;
+
+/// next is a xorshift generator so every run touches the same ids.
+fn next(state: &mut u64) -> u64 {
+    *state ^= *state << 13;
+    *state ^= *state >> 7;
+    *state ^= *state << 17;
+    *state
+}
+
+fn report(name: &str, frames: usize, elapsed: Duration) {
+    println!(
+        "{:<8} frames={:<8} {:>8.1} ns/op",
+        name,
+        frames,
+        elapsed.as_nanos() as f64 / OPS as f64
+    );
+}
+
+fn bench(frames: usize) {
+    let replacer = Replacer::<ReplacerNode>::new(frames);
+    for id in 0..frames {
+        replacer.insert_and_evict(id).unwrap();
+    }
+
+    let mut rng = 0x2545_f491_4f6c_dd1d;
+    let start = Instant::now();
+    for _ in 0..OPS {
+        let id = next(&mut rng) as usize % frames;
+        black_box(replacer.insert_and_evict(id).unwrap());
+    }
+    report("hit", frames, start.elapsed());
+
+    // Every id is new, so each access evicts a victim
+    let start = Instant::now();
+    for id in frames..frames + OPS {
+        black_box(replacer.insert_and_evict(id).unwrap());
+    }
+    report("miss", frames, start.elapsed());
+
+    let start = Instant::now();
+    for id in frames..frames + OPS {
+        black_box(replacer.remove(id));
+        black_box(replacer.insert_and_evict(id).unwrap());
+    }
+    report("reinsert", frames, start.elapsed());
+}
+
+fn main() {
+    for frames in [1 << 10, 100_000, 1 << 20] {
+        bench(frames);
+    }
+}
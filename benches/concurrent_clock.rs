@@ -0,0 +1,75 @@
+//! Compares the locked clock replacer with the atomic one as the
+//! number of threads goes up. Each thread pins, touches and unpins
+//! random frames the way the buffer pool does on a hit, and every
+//! tenth access is a miss that evicts a victim and reuses its frame.
+//! Run with `cargo bench --bench concurrent_clock`.
+
+use composter::replacement_policy::{Policy, ReplacementPolicy};
+use std::hint::black_box;
+use std::sync::Arc;
+use std::thread;
+use std::time::Instant;
+
+const FRAMES: usize = 1 << 16;
+const OPS_PER_THREAD: usize = 200_000;
+
+/// next is a xorshift generator so every run touches the same ids.
+fn next(state: &mut u64) -> u64 {
+    *state ^= *state << 13;
+    *state ^= *state >> 7;
+    *state ^= *state << 17;
+    *state
+}
+
+fn run(replacer: &dyn ReplacementPolicy, seed: u64) {
+    let mut rng = seed;
+    for op in 0..OPS_PER_THREAD {
+        if op % 10 == 9 {
+            if let Ok(frame_id) = replacer.evict() {
+                replacer.record_access(frame_id, op);
+            }
+            continue;
+        }
+
+        let frame_id = next(&mut rng) as usize % FRAMES;
+        replacer.record_access(frame_id, frame_id);
+        replacer.set_evictable(frame_id, false);
+        black_box(frame_id);
+        replacer.set_evictable(frame_id, true);
+    }
+}
+
+fn bench(policy: Policy, threads: usize) {
+    let replacer: Arc<dyn ReplacementPolicy> = Arc::new(policy.build(FRAMES));
+    for frame_id in 0..FRAMES {
+        replacer.record_access(frame_id, frame_id);
+    }
+
+    let start = Instant::now();
+    let handles = (0..threads)
+        .map(|thread| {
+            let replacer = replacer.clone();
+            thread::spawn(move || run(&*replacer, 0x2545_f491_4f6c_dd1d + thread as u64))
+        })
+        .collect::<Vec<_>>();
+    for handle in handles {
+        handle.join().unwrap();
+    }
+    let elapsed = start.elapsed();
+
+    let ops = (threads * OPS_PER_THREAD) as f64;
+    println!(
+        "{:<12} threads={:<3} {:>8.2} Mops/s",
+        format!("{:?}", policy),
+        threads,
+        ops / elapsed.as_secs_f64() / 1e6
+    );
+}
+
+fn main() {
+    for threads in [1, 4, 16, 32] {
+        for policy in [Policy::Clock, Policy::AtomicClock] {
+            bench(policy, threads);
+        }
+    }
+}
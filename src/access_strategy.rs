@@ -0,0 +1,112 @@
+use std::sync::{Mutex, MutexGuard, PoisonError};
+
+/// BULK_READ_RING_BYTES is the ring size for [AccessStrategy::BulkRead]
+const BULK_READ_RING_BYTES: usize = 256 * 1024;
+/// BULK_WRITE_RING_BYTES is the ring size for [AccessStrategy::BulkWrite]
+const BULK_WRITE_RING_BYTES: usize = 16 * 1024 * 1024;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AccessStrategy {
+    /// BulkRead is for large sequential scans.
+    BulkRead,
+    /// BulkWrite is for bulk loads, which dirty every page they touch
+    /// and so get a bigger ring to spread out the write backs.
+    BulkWrite,
+}
+
+impl AccessStrategy {
+    fn ring_bytes(&self) -> usize {
+        match self {
+            AccessStrategy::BulkRead => BULK_READ_RING_BYTES,
+            AccessStrategy::BulkWrite => BULK_WRITE_RING_BYTES,
+        }
+    }
+}
+
+/// [BufferAccessStrategy] is a small private ring of frames for one
+/// scan or bulk load. A miss made through the strategy recycles the
+/// frame it used a lap ago rather than asking the replacer for a
+/// victim, so the rest of the pool, and the working set in it, is
+/// left alone. A ring frame that someone else has since pinned or
+/// reused is given up and replaced with one from the pool.
+pub struct BufferAccessStrategy {
+    kind: AccessStrategy,
+    ring: Mutex<Ring>,
+}
+
+struct Ring {
+    /// slots hold the frame and the page the strategy read in to it
+    slots: Vec<Option<(usize, usize)>>,
+    current: usize,
+}
+
+impl BufferAccessStrategy {
+    /// new sizes the ring for the kind of access, never taking more
+    /// than an eighth of the pool.
+    pub fn new(kind: AccessStrategy, page_size: usize, num_frames: usize) -> Self {
+        let size = (kind.ring_bytes() / page_size.max(1))
+            .min(num_frames / 8)
+            .max(1);
+
+        Self {
+            kind,
+            ring: Mutex::new(Ring {
+                slots: vec![None; size],
+                current: 0,
+            }),
+        }
+    }
+
+    pub fn kind(&self) -> AccessStrategy {
+        self.kind
+    }
+
+    pub fn ring_size(&self) -> usize {
+        self.ring().slots.len()
+    }
+
+    /// next moves on to the next slot in the ring and returns the
+    /// frame and page it last held, if any.
+    pub(crate) fn next(&self) -> Option<(usize, usize)> {
+        let mut ring = self.ring();
+        ring.current = (ring.current + 1) % ring.slots.len();
+        ring.slots[ring.current]
+    }
+
+    /// put records the frame a page was read in to for the current
+    /// slot.
+    pub(crate) fn put(&self, frame_id: usize, page_id: usize) {
+        let mut ring = self.ring();
+        let current = ring.current;
+        ring.slots[current] = Some((frame_id, page_id));
+    }
+
+    fn ring(&self) -> MutexGuard<'_, Ring> {
+        self.ring.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::DEFAULT_PAGE_SIZE;
+
+    #[test]
+    fn test_buffer_access_strategy() {
+        let strategy = BufferAccessStrategy::new(AccessStrategy::BulkRead, DEFAULT_PAGE_SIZE, 4096);
+        assert_eq!(strategy.ring_size(), 64);
+        let strategy =
+            BufferAccessStrategy::new(AccessStrategy::BulkWrite, DEFAULT_PAGE_SIZE, 4096);
+        assert_eq!(strategy.ring_size(), 512);
+        let strategy = BufferAccessStrategy::new(AccessStrategy::BulkRead, DEFAULT_PAGE_SIZE, 4);
+        assert_eq!(strategy.ring_size(), 1);
+
+        let strategy = BufferAccessStrategy::new(AccessStrategy::BulkRead, DEFAULT_PAGE_SIZE, 16);
+        assert_eq!(strategy.next(), None);
+        strategy.put(3, 30);
+        assert_eq!(strategy.next(), None);
+        strategy.put(4, 40);
+        assert_eq!(strategy.next(), Some((3, 30)));
+        assert_eq!(strategy.next(), Some((4, 40)));
+    }
+}
@@ -0,0 +1,192 @@
+use crate::clock_replacer::EvictionError;
+use crate::replacement_policy::ReplacementPolicy;
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Mutex, MutexGuard, PoisonError};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum List {
+    /// T1 holds pages seen once since they were read in.
+    T1,
+    /// T2 holds pages seen at least twice.
+    T2,
+}
+
+/// [ArcReplacer] is the adaptive replacement cache policy from
+/// Megiddo and Modha. Resident pages are split between a recency list
+/// T1 and a frequency list T2, and evicted page ids are remembered in
+/// the ghost lists B1 and B2. A miss that hits a ghost list moves the
+/// target size of T1 towards whichever list would have kept the page.
+pub struct ArcReplacer {
+    capacity: usize,
+    state: Mutex<ArcState>,
+}
+
+#[derive(Default)]
+struct ArcState {
+    tick: u64,
+    frames: HashMap<usize, Entry>,
+    b1: VecDeque<usize>,
+    b2: VecDeque<usize>,
+    /// target is the size T1 is steered towards
+    target: usize,
+}
+
+struct Entry {
+    page_id: usize,
+    list: List,
+    last_access: u64,
+    evictable: bool,
+}
+
+impl ArcReplacer {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            state: Mutex::new(ArcState {
+                frames: HashMap::with_capacity(capacity),
+                ..Default::default()
+            }),
+        }
+    }
+
+    fn state(&self) -> MutexGuard<'_, ArcState> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl ArcState {
+    fn len(&self, list: List) -> usize {
+        self.frames
+            .values()
+            .filter(|entry| entry.list == list)
+            .count()
+    }
+
+    fn lru(&self, list: List) -> Option<usize> {
+        self.frames
+            .iter()
+            .filter(|(_, entry)| entry.list == list && entry.evictable)
+            .min_by_key(|(_, entry)| entry.last_access)
+            .map(|(frame_id, _)| *frame_id)
+    }
+
+    /// forget removes a page id from a ghost list, returning whether
+    /// it was there.
+    fn forget(ghosts: &mut VecDeque<usize>, page_id: usize) -> bool {
+        match ghosts.iter().position(|p_id| *p_id == page_id) {
+            Some(index) => {
+                ghosts.remove(index);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+impl ReplacementPolicy for ArcReplacer {
+    fn record_access(&self, frame_id: usize, page_id: usize) {
+        let state = &mut *self.state();
+        state.tick += 1;
+
+        if let Some(entry) = state.frames.get_mut(&frame_id) {
+            entry.list = List::T2;
+            entry.last_access = state.tick;
+            return;
+        }
+
+        let (b1, b2) = (state.b1.len().max(1), state.b2.len().max(1));
+        let list = if ArcState::forget(&mut state.b1, page_id) {
+            // T1 was too small to keep the page
+            state.target = (state.target + (b2 / b1).max(1)).min(self.capacity);
+            List::T2
+        } else if ArcState::forget(&mut state.b2, page_id) {
+            state.target = state.target.saturating_sub((b1 / b2).max(1));
+            List::T2
+        } else {
+            List::T1
+        };
+
+        state.frames.insert(
+            frame_id,
+            Entry {
+                page_id,
+                list,
+                last_access: state.tick,
+                evictable: true,
+            },
+        );
+    }
+
+    fn set_evictable(&self, frame_id: usize, evictable: bool) {
+        if let Some(entry) = self.state().frames.get_mut(&frame_id) {
+            entry.evictable = evictable;
+        }
+    }
+
+    fn evict(&self) -> Result<usize, EvictionError> {
+        let state = &mut *self.state();
+        let order = if state.len(List::T1) > state.target {
+            [List::T1, List::T2]
+        } else {
+            [List::T2, List::T1]
+        };
+
+        let frame_id = order
+            .into_iter()
+            .find_map(|list| state.lru(list))
+            .ok_or(EvictionError::NoFreeBuffer)?;
+        let entry = state.frames.remove(&frame_id).unwrap();
+        match entry.list {
+            List::T1 => state.b1.push_back(entry.page_id),
+            List::T2 => state.b2.push_back(entry.page_id),
+        }
+
+        // Each side remembers at most a cache worth of pages
+        let t1 = state.len(List::T1);
+        while t1 + state.b1.len() > self.capacity && state.b1.pop_front().is_some() {}
+        let t2 = state.len(List::T2);
+        while t2 + state.b2.len() > self.capacity && state.b2.pop_front().is_some() {}
+
+        Ok(frame_id)
+    }
+
+    fn remove(&self, frame_id: usize) {
+        self.state().frames.remove(&frame_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_arc_replacer() {
+        let replacer = ArcReplacer::new(4);
+        for frame_id in 0..4 {
+            replacer.record_access(frame_id, frame_id + 10);
+        }
+
+        // A second access moves 0 and 1 in to T2, out of a scan's way
+        replacer.record_access(0, 10);
+        replacer.record_access(1, 11);
+        for page_id in 20..30 {
+            let victim = replacer.evict().unwrap();
+            assert!(victim >= 2);
+            replacer.record_access(victim, page_id);
+        }
+        assert_eq!(replacer.state().target, 0);
+
+        // Page 27 comes back while B1 remembers it, so T1 was too small
+        let victim = replacer.evict().unwrap();
+        assert!(replacer.state().b1.contains(&27));
+        replacer.record_access(victim, 27);
+        assert_eq!(replacer.state().target, 1);
+        assert_eq!(replacer.state().frames[&victim].list, List::T2);
+
+        replacer.set_evictable(0, false);
+        replacer.remove(1);
+        replacer.remove(2);
+        replacer.remove(3);
+        assert!(matches!(replacer.evict(), Err(EvictionError::NoFreeBuffer)));
+    }
+}
@@ -0,0 +1,186 @@
+use crate::clock_replacer::{EvictionError, MAX_USAGE_COUNT};
+use crate::replacement_policy::ReplacementPolicy;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::{AtomicU32, Ordering};
+
+/// TRACKED is set while the frame holds a page the replacer knows of
+const TRACKED: u32 = 1 << 8;
+/// EVICTABLE is cleared while the frame is pinned
+const EVICTABLE: u32 = 1 << 9;
+/// USAGE masks the usage count in the low byte
+const USAGE: u32 = 0xff;
+
+/// [AtomicClockReplacer] is the CLOCK-sweep of
+/// [crate::clock_replacer::Replacer] without the lock. Each frame's
+/// usage count and flags share one atomic word and the clock hand is
+/// an atomic counter, so a hit is a single compare and swap and
+/// evictions from many threads sweep different frames at once. Frame
+/// ids index the words directly and must be below the capacity.
+pub struct AtomicClockReplacer {
+    frames: Vec<AtomicU32>,
+    hand: AtomicUsize,
+}
+
+impl AtomicClockReplacer {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            frames: (0..capacity).map(|_| AtomicU32::new(0)).collect(),
+            hand: AtomicUsize::new(0),
+        }
+    }
+
+    fn update(&self, frame_id: usize, f: impl FnMut(u32) -> Option<u32>) {
+        let _ = self.frames[frame_id].fetch_update(Ordering::AcqRel, Ordering::Acquire, f);
+    }
+}
+
+impl ReplacementPolicy for AtomicClockReplacer {
+    fn record_access(&self, frame_id: usize, _page_id: usize) {
+        self.update(frame_id, |word| {
+            if word & TRACKED == 0 {
+                return Some(TRACKED | EVICTABLE | 1);
+            }
+
+            let usage = word & USAGE;
+            (usage < MAX_USAGE_COUNT as u32).then_some(word + 1)
+        });
+    }
+
+    fn set_evictable(&self, frame_id: usize, evictable: bool) {
+        self.update(frame_id, |word| match (word & TRACKED != 0, evictable) {
+            (false, _) => None,
+            (true, true) => Some(word | EVICTABLE),
+            (true, false) => Some(word & !EVICTABLE),
+        });
+    }
+
+    /// evict advances the shared hand one frame at a time. An
+    /// evictable frame with a zero count is claimed by swapping its
+    /// word to zero, anything else in the way has its count taken
+    /// down by one. Losing a race to another thread just moves the
+    /// hand on. Enough steps to drain every count are taken before
+    /// giving up.
+    fn evict(&self) -> Result<usize, EvictionError> {
+        let capacity = self.frames.len();
+        for _ in 0..capacity * (MAX_USAGE_COUNT as usize + 2) {
+            let frame_id = self.hand.fetch_add(1, Ordering::Relaxed) % capacity;
+            let frame = &self.frames[frame_id];
+            let word = frame.load(Ordering::Acquire);
+            if word & (TRACKED | EVICTABLE) != TRACKED | EVICTABLE {
+                continue;
+            }
+
+            let next = match word & USAGE {
+                0 => 0,
+                _ => word - 1,
+            };
+            let swapped = frame
+                .compare_exchange(word, next, Ordering::AcqRel, Ordering::Acquire)
+                .is_ok();
+            if swapped && next == 0 {
+                return Ok(frame_id);
+            }
+        }
+
+        Err(EvictionError::NoFreeBuffer)
+    }
+
+    fn remove(&self, frame_id: usize) {
+        self.frames[frame_id].store(0, Ordering::Release);
+    }
+
+    /// ahead_of_hand reads the words ahead of the hand without
+    /// claiming them, so the answer may be stale by the time it is
+    /// used.
+    fn ahead_of_hand(&self, lookahead: usize) -> Option<Vec<usize>> {
+        let capacity = self.frames.len();
+        let hand = self.hand.load(Ordering::Relaxed);
+        let victims = (0..lookahead.min(capacity))
+            .map(|step| (hand + step) % capacity)
+            .filter(|&frame_id| {
+                let word = self.frames[frame_id].load(Ordering::Acquire);
+                word & (TRACKED | EVICTABLE) == TRACKED | EVICTABLE
+            })
+            .collect();
+
+        Some(victims)
+    }
+
+    fn capacity(&self) -> Option<usize> {
+        Some(self.frames.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn test_atomic_clock_replacer() {
+        let replacer = AtomicClockReplacer::new(4);
+        for frame_id in 0..4 {
+            replacer.record_access(frame_id, frame_id + 1);
+        }
+        replacer.record_access(0, 1);
+        replacer.set_evictable(1, false);
+
+        // 0 was used twice so the sweep reaches 2 first
+        assert_eq!(replacer.evict().unwrap(), 2);
+        assert_eq!(replacer.evict().unwrap(), 3);
+        assert_eq!(replacer.evict().unwrap(), 0);
+        assert!(matches!(replacer.evict(), Err(EvictionError::NoFreeBuffer)));
+
+        replacer.set_evictable(1, true);
+        assert_eq!(replacer.evict().unwrap(), 1);
+
+        // Untracked frames stay untracked
+        replacer.set_evictable(1, true);
+        replacer.remove(2);
+        assert!(matches!(replacer.evict(), Err(EvictionError::NoFreeBuffer)));
+    }
+
+    #[test]
+    fn test_atomic_clock_replacer_ahead_of_hand() {
+        let replacer = AtomicClockReplacer::new(4);
+        for frame_id in 0..4 {
+            replacer.record_access(frame_id, frame_id + 1);
+        }
+        replacer.record_access(3, 4);
+        replacer.set_evictable(0, false);
+        assert_eq!(replacer.evict().unwrap(), 1);
+
+        // The hand stopped at 2, 0 is pinned and 1 is gone
+        assert_eq!(replacer.ahead_of_hand(4), Some(vec![2, 3]));
+        assert_eq!(replacer.evict().unwrap(), 2);
+    }
+
+    #[test]
+    fn test_atomic_clock_replacer_concurrent_evict() {
+        let replacer = Arc::new(AtomicClockReplacer::new(64));
+        for frame_id in 0..64 {
+            replacer.record_access(frame_id, frame_id);
+        }
+
+        // Every frame is handed out exactly once however the threads
+        // interleave
+        let handles = (0..8)
+            .map(|_| {
+                let replacer = replacer.clone();
+                thread::spawn(move || {
+                    (0..8)
+                        .map(|_| replacer.evict().unwrap())
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect::<Vec<_>>();
+        let mut victims = handles
+            .into_iter()
+            .flat_map(|handle| handle.join().unwrap())
+            .collect::<Vec<_>>();
+        victims.sort();
+        assert_eq!(victims, (0..64).collect::<Vec<_>>());
+        assert!(matches!(replacer.evict(), Err(EvictionError::NoFreeBuffer)));
+    }
+}
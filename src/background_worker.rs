@@ -0,0 +1,107 @@
+use std::sync::{Arc, Condvar, Mutex, PoisonError};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/// [BackgroundWorker] runs a task on its own thread every delay until
+/// it is stopped or dropped. Stopping wakes the thread straight away
+/// rather than waiting out the delay.
+pub(crate) struct BackgroundWorker {
+    signal: Arc<StopSignal>,
+    handle: Option<JoinHandle<()>>,
+}
+
+/// [StopSignal] is how a worker is told to stop. Tasks that pace
+/// themselves sleep on it so they can give up early.
+#[derive(Default)]
+pub(crate) struct StopSignal {
+    stopped: Mutex<bool>,
+    wake: Condvar,
+}
+
+impl StopSignal {
+    /// sleep waits out delay unless the worker is stopped first, and
+    /// returns whether it is still running.
+    pub(crate) fn sleep(&self, delay: Duration) -> bool {
+        let stopped = self.stopped.lock().unwrap_or_else(PoisonError::into_inner);
+        let (stopped, _) = self
+            .wake
+            .wait_timeout_while(stopped, delay, |stopped| !*stopped)
+            .unwrap_or_else(PoisonError::into_inner);
+        !*stopped
+    }
+
+    fn stop(&self) {
+        *self.stopped.lock().unwrap_or_else(PoisonError::into_inner) = true;
+        self.wake.notify_all();
+    }
+}
+
+impl BackgroundWorker {
+    pub(crate) fn spawn(
+        delay: Duration,
+        mut task: impl FnMut(&StopSignal) + Send + 'static,
+    ) -> Self {
+        let signal = Arc::new(StopSignal::default());
+        let handle = {
+            let signal = Arc::clone(&signal);
+            thread::spawn(move || {
+                while signal.sleep(delay) {
+                    task(&signal);
+                }
+            })
+        };
+
+        Self {
+            signal,
+            handle: Some(handle),
+        }
+    }
+
+    /// stop waits for a task already running to finish. It is false
+    /// if the task panicked.
+    pub(crate) fn stop(&mut self) -> bool {
+        self.signal.stop();
+        self.handle
+            .take()
+            .is_none_or(|handle| handle.join().is_ok())
+    }
+}
+
+impl Drop for BackgroundWorker {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Instant;
+
+    #[test]
+    fn test_background_worker() {
+        let runs = Arc::new(AtomicUsize::new(0));
+        let mut worker = {
+            let runs = Arc::clone(&runs);
+            BackgroundWorker::spawn(Duration::from_millis(1), move |_| {
+                runs.fetch_add(1, Ordering::SeqCst);
+            })
+        };
+        while runs.load(Ordering::SeqCst) < 3 {
+            thread::yield_now();
+        }
+        assert!(worker.stop());
+
+        // Stopping doesn't wait out the delay, or a task's own sleep
+        let start = Instant::now();
+        let mut worker = BackgroundWorker::spawn(Duration::from_secs(60), |_| {});
+        assert!(worker.stop());
+        let mut worker = BackgroundWorker::spawn(Duration::ZERO, |signal| {
+            assert!(!signal.sleep(Duration::from_secs(60)));
+        });
+        thread::sleep(Duration::from_millis(10));
+        assert!(worker.stop());
+        assert!(start.elapsed() < Duration::from_secs(60));
+    }
+}
@@ -1,133 +1,378 @@
-use crate::clock_replacer::{Evictable, Replacer};
-use crate::disk_manager::DiskManager;
+use crate::access_strategy::{AccessStrategy, BufferAccessStrategy};
+use crate::background_worker::{BackgroundWorker, StopSignal};
+use crate::clock_replacer::Evictable;
+use crate::disk_manager::IoPriority;
 use crate::disk_scheduler::DiskScheduler;
-use std::cell::RefCell;
-use std::collections::HashMap;
-use std::io::{ErrorKind, Read, Write};
+use crate::error::{Error, Result};
+use crate::page_latch::PageLatch;
+use crate::replacement_policy::{Policy, ReplacementPolicy};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::future::Future;
+use std::io::{ErrorKind, Read, Seek, SeekFrom, Write};
 use std::ops::{Deref, DerefMut};
+use std::pin::Pin;
 use std::sync::atomic::Ordering::Relaxed;
 use std::sync::atomic::{AtomicU64, AtomicUsize};
-use std::sync::{Arc, LockResult, Mutex, RwLock};
-
-enum CheckedPage {
-    Read(ReadPage),
-    Write(WritePage),
-}
+use std::sync::{mpsc, Arc, Condvar, Mutex, MutexGuard, PoisonError, RwLock};
+use std::task::{Context, Poll, Waker};
+use std::time::Duration;
 
+/// [ReadPage] shares a page with any other readers. The page can't
+/// be written to or evicted while it is held. It derefs to the page's
+/// bytes, and reads start at the position last seeked to.
 pub struct ReadPage {
     pub page_id: usize,
     pub pinned: AtomicUsize,
-    pub frame: Arc<Mutex<Frame>>,
+    pub(crate) frame: Arc<RwLock<Frame>>,
+    pin: Option<FramePin>,
+    position: usize,
 }
 
 impl ReadPage {
-    pub fn new(page_id: usize, pinned: AtomicUsize, frame: Arc<Mutex<Frame>>) -> Self {
+    /// new wraps a frame that isn't shared with the pool, so it has
+    /// no pin or latch to hold.
+    #[cfg(test)]
+    pub(crate) fn new(page_id: usize, pinned: AtomicUsize, frame: Arc<RwLock<Frame>>) -> Self {
         Self {
             page_id,
             pinned,
             frame,
+            pin: None,
+            position: 0,
+        }
+    }
+
+    pub fn is_dirty(&self) -> Result<bool> {
+        let frame = self.frame.read()?;
+        Ok(frame.dirty)
+    }
+
+    /// upgrade waits for the other readers to leave and turns the
+    /// guard in to a [WritePage]. If another reader is already
+    /// waiting to upgrade the two would wait on each other forever,
+    /// so the guard is handed back instead.
+    pub fn upgrade(self) -> std::result::Result<WritePage, ReadPage> {
+        match &self.pin {
+            Some(pin) if !pin.latch.upgrade() => Err(self),
+            _ => Ok(self.into_write()),
+        }
+    }
+
+    /// try_upgrade is [ReadPage::upgrade] without the wait. It only
+    /// succeeds for the page's sole reader.
+    pub fn try_upgrade(self) -> std::result::Result<WritePage, ReadPage> {
+        match &self.pin {
+            Some(pin) if !pin.latch.try_upgrade() => Err(self),
+            _ => Ok(self.into_write()),
         }
     }
 
-    pub fn is_dirty(&self) -> Result<bool, std::io::Error> {
-        let frame = self.frame.lock();
-        match frame {
-            Ok(frame) => Ok(frame.dirty),
-            Err(err) => Err(std::io::Error::new(ErrorKind::Other, err.to_string())),
+    fn into_write(self) -> WritePage {
+        let ReadPage {
+            page_id,
+            pinned,
+            frame,
+            mut pin,
+            position,
+        } = self;
+        if let Some(pin) = &mut pin {
+            pin.exclusive = true;
+        }
+
+        WritePage {
+            page_id,
+            pinned,
+            frame,
+            pin,
+            position,
         }
     }
 }
 
+impl Deref for ReadPage {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        // SAFETY: the page latch keeps writers out for as long as
+        // the guard lives, and see page_buffer
+        unsafe { &*page_buffer(&self.frame, false) }
+    }
+}
+
 impl Read for ReadPage {
     fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
-        let frame = self.frame.lock();
-        match frame {
-            Ok(frame) => {
-                let frame_buf = &frame.buffer;
-                buf.copy_from_slice(frame_buf);
-                Ok(buf.len())
-            }
-            Err(err) => Err(std::io::Error::new(ErrorKind::Other, err.to_string())),
-        }
+        let page = &**self;
+        let start = self.position.min(page.len());
+        let read = buf.len().min(page.len() - start);
+        buf[..read].copy_from_slice(&page[start..start + read]);
+        self.position = start + read;
+        Ok(read)
     }
 }
 
-impl Drop for ReadPage {
-    fn drop(&mut self) {
-        let frame = self.frame.lock();
-        match frame {
-            Ok(frame) => {
-                frame.pin_count.fetch_sub(1, Relaxed);
-            }
-            Err(err) => {
-                eprintln!("Error occurred during drop: {}", err);
-            }
-        }
+impl Seek for ReadPage {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        self.position = seek_position(self.position, self.len(), pos)?;
+        Ok(self.position as u64)
     }
 }
 
+/// [WritePage] has a page to itself. No reader or other writer can
+/// get at the page until it is dropped or downgraded. It derefs to
+/// the page's bytes, and writes go to the position last seeked to.
 pub struct WritePage {
     pub page_id: usize,
     pub pinned: AtomicUsize,
-    pub frame: Arc<Mutex<Frame>>,
+    pub(crate) frame: Arc<RwLock<Frame>>,
+    pin: Option<FramePin>,
+    position: usize,
 }
 
 impl WritePage {
-    pub fn new(page_id: usize, pinned: AtomicUsize, frame: Arc<Mutex<Frame>>) -> Self {
+    /// new wraps a frame that isn't shared with the pool, so it has
+    /// no pin or latch to hold.
+    #[cfg(test)]
+    pub(crate) fn new(page_id: usize, pinned: AtomicUsize, frame: Arc<RwLock<Frame>>) -> Self {
         Self {
             page_id,
             pinned,
             frame,
+            pin: None,
+            position: 0,
         }
     }
 
-    pub fn is_dirty(&self) -> Result<bool, std::io::Error> {
-        let frame = self.frame.lock();
-        match frame {
-            Ok(frame) => Ok(frame.dirty),
-            Err(err) => Err(std::io::Error::new(ErrorKind::Other, err.to_string())),
+    pub fn is_dirty(&self) -> Result<bool> {
+        let frame = self.frame.read()?;
+        Ok(frame.dirty)
+    }
+
+    /// downgrade turns the guard in to a [ReadPage] without letting
+    /// another writer in between.
+    pub fn downgrade(self) -> ReadPage {
+        let WritePage {
+            page_id,
+            pinned,
+            frame,
+            mut pin,
+            position,
+        } = self;
+        if let Some(pin) = &mut pin {
+            pin.latch.downgrade();
+            pin.exclusive = false;
+        }
+
+        ReadPage {
+            page_id,
+            pinned,
+            frame,
+            pin,
+            position,
         }
     }
 }
 
+impl Deref for WritePage {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        // SAFETY: the guard holds the page latch exclusively, and see
+        // page_buffer
+        unsafe { &*page_buffer(&self.frame, false) }
+    }
+}
+
+impl DerefMut for WritePage {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        // SAFETY: as for deref, and the &mut self borrow keeps this
+        // the only slice handed out by the guard
+        unsafe { &mut *page_buffer(&self.frame, true) }
+    }
+}
+
 impl Write for WritePage {
-    fn write(&mut self, data: &[u8]) -> Result<usize, std::io::Error> {
-        let frame = &mut self.frame.lock();
-        match frame {
-            Ok(frame) => {
-                frame.dirty = true;
-                frame.write(data)
-            }
-            Err(err) => Err(std::io::Error::new(ErrorKind::Other, err.to_string())),
+    /// write only takes the page for writing, and marks it dirty,
+    /// when there is something to write.
+    fn write(&mut self, data: &[u8]) -> std::io::Result<usize> {
+        let len = self.len();
+        let start = self.position.min(len);
+        let written = data.len().min(len - start);
+        if written > 0 {
+            self[start..start + written].copy_from_slice(&data[..written]);
         }
+        self.position = start + written;
+        Ok(written)
     }
 
+    /// flush writes the page to disk if it is dirty and syncs the
+    /// data file. The page stays latched for the whole write.
     fn flush(&mut self) -> std::io::Result<()> {
-        unimplemented!()
+        match &self.pin {
+            Some(pin) => Ok(pin.flush()?),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Seek for WritePage {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        self.position = seek_position(self.position, self.len(), pos)?;
+        Ok(self.position as u64)
+    }
+}
+
+/// page_buffer points at a frame's buffer past the frame lock, marking
+/// the frame dirty when the pointer is for writing. Once a frame is
+/// pinned its buffer is never moved or resized, and its bytes are
+/// only read or written by holders of the page latch, so a guard can
+/// use the pointer for as long as it lives.
+///
+/// Only a write deref takes the frame lock exclusively, so readers of
+/// a page don't queue up behind each other on it.
+fn page_buffer(frame: &RwLock<Frame>, dirty: bool) -> *mut [u8] {
+    if !dirty {
+        let frame = frame.read().unwrap_or_else(PoisonError::into_inner);
+        return std::ptr::slice_from_raw_parts_mut(
+            frame.buffer.as_ptr().cast_mut(),
+            frame.buffer.len(),
+        );
+    }
+    let mut frame = frame.write().unwrap_or_else(PoisonError::into_inner);
+    frame.dirty = true;
+    std::ptr::slice_from_raw_parts_mut(frame.buffer.as_mut_ptr(), frame.buffer.len())
+}
+
+/// seek_position works out where a seek on a page guard lands. Seeking
+/// past the end of the page is allowed, reads and writes there just
+/// come back empty.
+fn seek_position(position: usize, len: usize, pos: SeekFrom) -> std::io::Result<usize> {
+    let target = match pos {
+        SeekFrom::Start(offset) => usize::try_from(offset).ok(),
+        SeekFrom::End(offset) => len.checked_add_signed(offset as isize),
+        SeekFrom::Current(offset) => position.checked_add_signed(offset as isize),
+    };
+    target.ok_or_else(|| {
+        std::io::Error::new(
+            ErrorKind::InvalidInput,
+            "invalid seek to a negative or overflowing position",
+        )
+    })
+}
+
+/// [FramePin] is what a guard handed out by the pool holds on its
+/// frame: the pin that keeps it resident and the page latch. Both
+/// are let go when the guard is dropped.
+struct FramePin {
+    frame_id: usize,
+    frame: Arc<RwLock<Frame>>,
+    replacer: Arc<dyn ReplacementPolicy>,
+    disk_scheduler: Arc<DiskScheduler>,
+    latch: Arc<PageLatch>,
+    exclusive: bool,
+}
+
+impl FramePin {
+    fn flush(&self) -> Result<()> {
+        // The frame lock is let go before the write, hits on other
+        // pages of the partition lock the frame to pin it
+        let copy = take_dirty(&mut *self.frame.write()?, None);
+        if let Some(copy) = copy {
+            WriteBack::queue(&self.disk_scheduler, IoPriority::ForegroundWrite, copy)
+                .wait(&self.frame)?;
+        }
+
+        self.disk_scheduler.sync()
     }
 }
 
-impl Drop for WritePage {
+impl Drop for FramePin {
     fn drop(&mut self) {
-        let frame = &mut self.frame.lock();
-        match frame {
-            Ok(frame) => {
-                frame.pin_count.fetch_sub(1, Relaxed);
-            }
-            Err(err) => {
-                panic!("Error occurred during drop: {}", err);
+        if self.exclusive {
+            self.latch.unlock_exclusive();
+        } else {
+            self.latch.unlock_shared();
+        }
+
+        unpin(&self.frame, self.frame_id, &*self.replacer);
+    }
+}
+
+/// take_dirty copies out the page of a dirty frame, if it is page_id
+/// when one is given, and marks the frame clean. Writes made after
+/// the copy dirty it again. The caller must hold the page latch.
+fn take_dirty(frame: &mut Frame, page_id: Option<usize>) -> Option<(usize, Vec<u8>)> {
+    let current = frame
+        .current_page_index
+        .filter(|current| frame.dirty && page_id.is_none_or(|page_id| page_id == *current))?;
+    frame.dirty = false;
+
+    Some((current, frame.buffer.clone()))
+}
+
+/// [WriteBack] is the write of a page copied by take_dirty. It is
+/// queued before the page latch is let go, and the scheduler
+/// completes writes to a page in the order they were queued, so the
+/// latest copy of a page is always the last to land.
+struct WriteBack(Result<mpsc::Receiver<Result<()>>>);
+
+impl WriteBack {
+    fn queue(
+        disk_scheduler: &DiskScheduler,
+        priority: IoPriority,
+        (page_id, data): (usize, Vec<u8>),
+    ) -> Self {
+        let (tx, rx) = mpsc::channel();
+        let data = Arc::new(Mutex::new(data));
+        let queued = disk_scheduler.request_with_priority(priority, true, data, page_id, tx);
+        WriteBack(queued.map(|_| rx))
+    }
+
+    /// wait waits for the write, marking the frame dirty again if it
+    /// failed.
+    fn wait(self, frame: &RwLock<Frame>) -> Result<()> {
+        let result = self
+            .0
+            .and_then(|rx| rx.recv().map_err(|_| Error::Disconnected)?);
+        if result.is_err() {
+            frame.write()?.dirty = true;
+        }
+
+        result
+    }
+}
+
+/// pin takes a pin on a locked frame. A pinned frame is never picked
+/// as a victim.
+fn pin(frame: &Frame, frame_id: usize, replacer: &dyn ReplacementPolicy) {
+    if frame.pin_count.fetch_add(1, Relaxed) == 0 {
+        replacer.set_evictable(frame_id, false);
+    }
+}
+
+/// unpin drops a pin. The frame only becomes evictable again when no
+/// other guard holds it. Pins are only taken under the frame's write
+/// lock, so one can't slip in between the count reaching zero and
+/// the replacer being told.
+fn unpin(frame: &RwLock<Frame>, frame_id: usize, replacer: &dyn ReplacementPolicy) {
+    match frame.read() {
+        Ok(frame) => {
+            if frame.pin_count.fetch_sub(1, Relaxed) == 1 {
+                replacer.set_evictable(frame_id, true);
             }
-        };
+        }
+        Err(err) => {
+            eprintln!("Error occurred during drop: {}", err);
+        }
     }
 }
 
 #[derive(Clone)]
-struct ReplacerNode {
+pub struct ReplacerNode {
     frame_id: usize,
     evictable: bool,
 }
 
-impl Evictable<ReplacerNode> for ReplacerNode {
+impl Evictable for ReplacerNode {
     fn new(id: usize) -> ReplacerNode {
         ReplacerNode {
             frame_id: id,
@@ -136,7 +381,7 @@ impl Evictable<ReplacerNode> for ReplacerNode {
     }
 
     fn pinned(&self) -> bool {
-        self.evictable
+        !self.evictable
     }
 
     fn id(&self) -> usize {
@@ -152,37 +397,6 @@ pub struct Frame {
     pub dirty: bool,
 }
 
-impl Write for Frame {
-    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
-        if buf.len() > self.page_size {
-            return Err(std::io::Error::new(
-                ErrorKind::OutOfMemory,
-                "buffer is too large",
-            ));
-        }
-        let curr_len = &self
-            .buffer
-            .iter()
-            .take_while(|x| *x != &0)
-            .collect::<Vec<_>>();
-        let curr_len = curr_len.len();
-        let total_len = curr_len + buf.len();
-        if total_len > self.page_size {
-            return Err(std::io::Error::new(
-                ErrorKind::OutOfMemory,
-                "buffer will exceed page size",
-            ));
-        }
-
-        self.buffer[curr_len..total_len].copy_from_slice(buf);
-        Ok(buf.len())
-    }
-
-    fn flush(&mut self) -> std::io::Result<()> {
-        unimplemented!()
-    }
-}
-
 impl Frame {
     fn new(page_size: usize) -> Self {
         let buffer = vec![0; page_size];
@@ -196,278 +410,2214 @@ impl Frame {
     }
 }
 
-struct BufferPoolManager {
-    disk_scheduler: DiskScheduler,
-    page_table: Arc<Mutex<HashMap<usize, usize>>>,
+/// DEFAULT_PAGE_TABLE_PARTITIONS is the number of hash partitions the
+/// page table is split in to unless the pool is built with
+/// [BufferPoolManager::with_partitions].
+pub const DEFAULT_PAGE_TABLE_PARTITIONS: usize = 16;
+
+pub struct BufferPoolManager {
+    disk_scheduler: Arc<DiskScheduler>,
+    /// partitions split the page table by page id, so lookups of
+    /// different pages rarely share a page table lock
+    partitions: Arc<[Mutex<Partition>]>,
+    /// free_list also serialises misses while they claim a frame
     free_list: Arc<Mutex<Vec<usize>>>,
     current_page_index: AtomicUsize,
-    replacer: Arc<Mutex<Replacer<ReplacerNode>>>,
-    frames: Vec<Arc<Mutex<Frame>>>,
+    /// replacer is told of every hit. Only [Policy::AtomicClock]
+    /// does that without a lock, with any other policy hits on
+    /// different pages still meet on the replacer's lock
+    replacer: Arc<dyn ReplacementPolicy>,
+    frames: Arc<[Arc<RwLock<Frame>>]>,
+    /// latches are the page latches of the frames, held by the guards
+    latches: Arc<[Arc<PageLatch>]>,
     page_size: usize,
+    background_writer: Mutex<Option<BackgroundWorker>>,
+    checkpointer: Mutex<Option<BackgroundWorker>>,
 }
 
-impl BufferPoolManager {
-    pub fn new(
-        disk_scheduler: DiskScheduler,
-        replacer: Replacer<ReplacerNode>,
-        page_size: usize,
-        num_frames: usize,
-    ) -> BufferPoolManager {
-        let page_table = HashMap::new();
-        let current_page_index = AtomicUsize::new(0);
-        let frames = (0..num_frames)
-            .map(|_| Arc::new(Mutex::new(Frame::new(page_size))))
-            .collect::<Vec<_>>();
-        let free_list = (0..num_frames).collect();
+/// [BackgroundWriterConfig] sets the pace of the background writer,
+/// after Postgres' bgwriter_delay and bgwriter_lru_maxpages.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BackgroundWriterConfig {
+    /// delay is the pause between rounds.
+    pub delay: Duration,
+    /// lookahead is how many frames ahead of the clock hand a round
+    /// looks at.
+    pub lookahead: usize,
+    /// max_pages is the most pages a round writes.
+    pub max_pages: usize,
+}
 
-        BufferPoolManager {
-            disk_scheduler,
-            page_table: Arc::new(Mutex::new(page_table)),
-            free_list: Arc::new(Mutex::new(free_list)),
-            current_page_index,
-            replacer: Arc::new(Mutex::new(replacer)),
-            frames,
-            page_size,
+impl Default for BackgroundWriterConfig {
+    fn default() -> Self {
+        Self {
+            delay: Duration::from_millis(200),
+            lookahead: 256,
+            max_pages: 100,
         }
     }
+}
 
-    /// new_page creates a new page entry on disk
-    /// increasing the file size of the page file
-    /// it returns the page_id
-    pub fn new_page(&mut self) -> usize {
-        self.current_page_index.fetch_add(1, Relaxed);
-        self.disk_scheduler.new_page(self.page_size);
-
-        self.current_page_index.load(Relaxed)
-    }
+/// [CheckpointConfig] sets the pace of the checkpointer, after
+/// Postgres' checkpoint_timeout and checkpoint_completion_target.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CheckpointConfig {
+    /// interval is the pause between one checkpoint finishing and the
+    /// next one starting.
+    pub interval: Duration,
+    /// spread is about how long a checkpoint takes, its writes are
+    /// spaced out evenly over it.
+    pub spread: Duration,
+}
 
-    pub fn read_page(&self, page_id: usize) -> Option<ReadPage> {
-        if let Some(frame_id) = self.check_page(page_id) {
-            if let Some(frame) = self.frames.get(frame_id) {
-                let frame_copy = Arc::clone(frame);
-                Some(ReadPage {
-                    page_id,
-                    pinned: AtomicUsize::new(1),
-                    frame: frame_copy,
-                })
-            } else {
-                None
-            }
-        } else {
-            None
+impl Default for CheckpointConfig {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_secs(300),
+            spread: Duration::from_secs(150),
         }
     }
+}
 
-    pub fn write_page(&self, page_id: usize) -> Option<WritePage> {
-        if let Some(frame_id) = self.check_page(page_id) {
-            if let Some(frame) = self.frames.get(frame_id) {
-                let frame_copy = Arc::clone(frame);
-                Some(WritePage {
-                    page_id,
-                    pinned: AtomicUsize::new(1),
-                    frame: frame_copy,
-                })
-            } else {
-                None
-            }
-        } else {
-            None
-        }
-    }
+/// [Partition] is one hash partition of the page table.
+#[derive(Default)]
+struct Partition {
+    page_table: HashMap<usize, usize>,
+    /// loading holds the pages a miss is reading in or writing back.
+    /// Anyone else after one of them waits for the load to finish.
+    loading: HashMap<usize, Arc<InFlightLoad>>,
+}
 
-    /// check_page checks if the requests page
-    /// is already mapped to a frame. If it is not
-    /// eviction can occur and a frame will be freed
-    /// which then a new page will be brought in.  
-    fn check_page(&self, page_id: usize) -> Option<usize> {
-        if page_id > self.current_page_index.load(Relaxed) {
-            return None;
-        }
-
-        let mut page_table = self.page_table.try_lock().unwrap();
-        let mut free_list = self.free_list.try_lock().unwrap();
-        let mut replacer = self.replacer.try_lock().unwrap();
-
-        match page_table.get(&page_id) {
-            Some(frame_id) => Some(*frame_id),
-            None => {
-                let free_frame = free_list.get(page_id);
-                if free_frame.is_some() {
-                    return free_frame.copied();
-                }
+/// partition_index is the partition page_id's entries live in. The
+/// id is hashed first, a sharded pool only routes ids with the same
+/// remainder to an instance and those would all land in a few
+/// partitions otherwise.
+fn partition_index(page_id: usize, count: usize) -> usize {
+    let hash = (page_id as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15) >> 32;
+    hash as usize % count
+}
 
-                let evicted_frame = replacer
-                    .insert_and_evict(page_id)
-                    .expect("failed to evict page");
-                if let Some(item) = evicted_frame {
-                    free_list.push(item.id());
-                    page_table.remove(&page_id);
-                }
-                None
-            }
-        }
+impl Partition {
+    fn contains(&self, page_id: usize) -> bool {
+        self.page_table.contains_key(&page_id) || self.loading.contains_key(&page_id)
     }
 }
 
-mod tests {
-    use super::*;
-    use crate::DEFAULT_PAGE_SIZE;
-    use std::cell::RefCell;
-    use std::io::Error;
-    use std::sync::{Arc, Mutex};
-    #[test]
-    fn new_buffer_pool_manager() {
-        let disk_manager = Arc::new(Mutex::new(DiskManager::default()));
-        let disk_scheduler = DiskScheduler::new(disk_manager);
-        let replacer = Replacer::new(10);
-        let buffer_pool_manager =
-            BufferPoolManager::new(disk_scheduler, replacer, DEFAULT_PAGE_SIZE, 10);
+/// [LockedPartitions] holds the partitions of the pages a miss
+/// touches. They are always locked in index order so two misses
+/// can't deadlock.
+struct LockedPartitions<'a> {
+    guards: Vec<(usize, MutexGuard<'a, Partition>)>,
+    count: usize,
+}
 
-        assert_eq!(buffer_pool_manager.frames.len(), 10);
+impl LockedPartitions<'_> {
+    fn get(&mut self, page_id: usize) -> &mut Partition {
+        let index = partition_index(page_id, self.count);
+        let (_, partition) = self
+            .guards
+            .iter_mut()
+            .find(|(i, _)| *i == index)
+            .expect("partition of a page the miss didn't lock");
+        partition
     }
+}
 
-    #[test]
-    fn add_pages() {
-        let disk_manager = Arc::new(Mutex::new(DiskManager::default()));
-        let disk_scheduler = DiskScheduler::new(disk_manager);
-        let replacer = Replacer::new(10);
-        let mut buffer_pool_manager =
-            BufferPoolManager::new(disk_scheduler, replacer, DEFAULT_PAGE_SIZE, 10);
+enum Lookup<'a> {
+    Hit(usize),
+    /// The page is being loaded by someone else.
+    Wait(Arc<InFlightLoad>),
+    Miss(PageLoad<'a>),
+}
 
-        assert_eq!(buffer_pool_manager.frames.len(), 10);
+/// [InFlightLoad] is shared between a miss and everyone who asks for
+/// the same page while it is loading. Waiters look the page up again
+/// once the load is done, whether it succeeded or not.
+#[derive(Default)]
+struct InFlightLoad {
+    state: Mutex<LoadState>,
+    done: Condvar,
+}
 
-        // Create 10 new pages in memory for this test
-        // return the page index.
-        for i in 1..11 {
-            let np = buffer_pool_manager.new_page();
-            assert_eq!(np, i);
+#[derive(Default)]
+struct LoadState {
+    done: bool,
+    wakers: Vec<Waker>,
+}
+
+impl InFlightLoad {
+    fn wait(&self) -> Result<()> {
+        let mut state = self.state.lock()?;
+        while !state.done {
+            state = self.done.wait(state)?;
         }
+
+        Ok(())
     }
 
-    #[test]
-    fn test_frame_writer() {
-        let mut frame = Frame::new(5);
-        assert_eq!(vec![0, 0, 0, 0, 0], frame.buffer);
-        let val = frame.write(&[1, 2, 3]).unwrap();
-        assert_eq!(val, 3);
-        assert_eq!(vec![1, 2, 3, 0, 0], frame.buffer);
-        let val = frame.write(&[4]).unwrap();
-        assert_eq!(val, 1);
-        assert_eq!(vec![1, 2, 3, 4, 0], frame.buffer);
-        let val = frame.write(&[5]).unwrap();
-        assert_eq!(val, 1);
-        assert_eq!(vec![1, 2, 3, 4, 5], frame.buffer);
-        match frame.write(&[6]) {
-            Ok(_val) => {
-                panic!("should not have a value")
-            }
-            Err(err) => {
-                assert_eq!(err.to_string(), "buffer will exceed page size");
+    fn signal(&self) {
+        let wakers = match self.state.lock() {
+            Ok(mut state) => {
+                state.done = true;
+                std::mem::take(&mut state.wakers)
             }
+            Err(_) => vec![],
+        };
+
+        self.done.notify_all();
+        for waker in wakers {
+            waker.wake();
         }
     }
+}
 
-    #[test]
-    fn test_read_write_page_frame() {
-        let mut frame = Arc::new(Mutex::new(Frame::new(5)));
-        let f = Arc::clone(&frame);
-        assert!(!f.lock().unwrap().dirty);
+/// [LoadWait] is [InFlightLoad::wait] for async callers.
+struct LoadWait(Arc<InFlightLoad>);
 
-        let mut wp = WritePage {
-            page_id: 1,
-            pinned: Default::default(),
-            frame: f,
-        };
+impl Future for LoadWait {
+    type Output = Result<()>;
 
-        let a = wp.write(&[97, 97]).unwrap();
-        assert_eq!(a, 2);
-        let is_dirty = wp.is_dirty().unwrap();
-        assert!(is_dirty);
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut state = self.0.state.lock()?;
+        if state.done {
+            return Poll::Ready(Ok(()));
+        }
 
-        drop(wp);
+        if !state.wakers.iter().any(|waker| waker.will_wake(cx.waker())) {
+            state.wakers.push(cx.waker().clone());
+        }
+        Poll::Pending
+    }
+}
 
-        let mut rp = ReadPage {
-            page_id: 1,
-            pinned: Default::default(),
-            frame,
-        };
+/// [PageLoad] owns a frame while a miss reads a page in to it. The
+/// IO goes through a copy of the frame buffer so the frame isn't
+/// locked while the disk is busy. Dropping an unfinished load, on an
+/// error or because its future was dropped, hands the frame back.
+/// Either way the waiters are woken once it is dropped.
+struct PageLoad<'a> {
+    buffer_pool_manager: &'a BufferPoolManager,
+    frame_id: usize,
+    page_id: usize,
+    /// victim is the dirty page that has to be written back first
+    victim: Option<usize>,
+    victim_written: bool,
+    data: Arc<Mutex<Vec<u8>>>,
+    in_flight: Arc<InFlightLoad>,
+    finished: bool,
+}
 
-        let mut buf = vec![0; 5];
-        let buf_read = rp.read(&mut buf);
-        assert!(buf_read.is_ok());
-        assert_eq!(buf_read.unwrap(), 5);
-        assert_eq!(buf, vec![97, 97, 0, 0, 0]);
-    }
+impl PageLoad<'_> {
+    fn finish(mut self) -> Result<usize> {
+        self.finished = true;
+        let bpm = self.buffer_pool_manager;
+        let mut partitions = bpm.lock_partitions(&[Some(self.page_id), self.victim])?;
+        let mut frame = bpm.frames[self.frame_id].write()?;
 
-    #[test]
-    fn test_read_write_page() {
-        let disk_manager = Arc::new(Mutex::new(DiskManager::default()));
-        let disk_scheduler = DiskScheduler::new(disk_manager);
-        let replacer = Replacer::new(10);
-        let mut buffer_pool_manager =
-            BufferPoolManager::new(disk_scheduler, replacer, DEFAULT_PAGE_SIZE, 10);
+        frame.buffer = std::mem::take(&mut *self.data.lock()?);
+        frame.current_page_index = Some(self.page_id);
+        let partition = partitions.get(self.page_id);
+        partition.page_table.insert(self.page_id, self.frame_id);
+        partition.loading.remove(&self.page_id);
+        bpm.replacer.record_access(self.frame_id, self.page_id);
+        bpm.pin(&frame, self.frame_id);
+        if let Some(victim) = self.victim {
+            partitions.get(victim).loading.remove(&victim);
+        }
 
-        let np = buffer_pool_manager.new_page();
-        assert_eq!(np, 1);
-        let np2 = buffer_pool_manager.new_page();
-        assert_eq!(np2, 2);
-        let rp = buffer_pool_manager.read_page(np);
-        assert!(rp.is_some());
-        let rp2 = buffer_pool_manager.read_page(10);
-        assert!(rp2.is_none());
+        Ok(self.frame_id)
+    }
 
-        // Write to a page and then read from it
-        let wp = buffer_pool_manager.write_page(np);
-        assert!(wp.is_some());
+    fn abort(&self) -> Result<()> {
+        let bpm = self.buffer_pool_manager;
+        let mut free_list = bpm.free_list.lock()?;
+        let mut partitions = bpm.lock_partitions(&[Some(self.page_id), self.victim])?;
+        let mut frame = bpm.frames[self.frame_id].write()?;
 
-        match wp.unwrap().write(b"foo") {
-            Ok(written) => {
-                assert_eq!(written, 3);
+        match self.victim {
+            // The victim may not have reached disk, keep it resident
+            // rather than lose its contents. The buffer is copied as a
+            // queued request could still be using it
+            Some(victim) if !self.victim_written => {
+                frame.buffer.copy_from_slice(&self.data.lock()?);
+                frame.current_page_index = Some(victim);
+                frame.dirty = true;
+                partitions
+                    .get(victim)
+                    .page_table
+                    .insert(victim, self.frame_id);
+                bpm.replacer.record_access(self.frame_id, victim);
             }
-            Err(err) => {
-                panic!("failed to write buffer page: {}", err);
+            _ => {
+                frame.buffer.fill(0);
+                free_list.push(self.frame_id);
             }
         }
 
-        let rp = buffer_pool_manager.read_page(np);
-        assert!(rp.is_some());
-        let mut buf = [0_u8; DEFAULT_PAGE_SIZE];
-
-        match rp.unwrap().read(&mut buf) {
-            Ok(_written) => {}
-            Err(err) => {
-                panic!("failed to write buffer page: {}", err);
-            }
+        partitions.get(self.page_id).loading.remove(&self.page_id);
+        if let Some(victim) = self.victim {
+            partitions.get(victim).loading.remove(&victim);
         }
 
-        assert_eq!(buf[..3], [102, 111, 111]);
-
-        let wp = buffer_pool_manager.write_page(np);
-        assert!(wp.is_some());
+        Ok(())
+    }
+}
 
-        match wp.unwrap().write(b"bar") {
-            Ok(written) => {
-                assert_eq!(written, 3);
-            }
-            Err(err) => {
-                panic!("failed to write buffer page: {}", err);
+impl Drop for PageLoad<'_> {
+    fn drop(&mut self) {
+        if !self.finished {
+            if let Err(err) = self.abort() {
+                eprintln!("Error occurred during drop: {}", err);
             }
         }
 
-        let rp = buffer_pool_manager.read_page(np);
-        assert!(rp.is_some());
-        let mut buf = [0_u8; DEFAULT_PAGE_SIZE];
+        self.in_flight.signal();
+    }
+}
 
-        match rp.unwrap().read(&mut buf) {
-            Ok(_written) => {}
-            Err(err) => {
-                panic!("failed to write buffer page: {}", err);
-            }
+impl BufferPoolManager {
+    pub fn new(
+        disk_scheduler: DiskScheduler,
+        replacer: impl ReplacementPolicy + 'static,
+        page_size: usize,
+        num_frames: usize,
+    ) -> Result<BufferPoolManager> {
+        Self::with_partitions(
+            disk_scheduler,
+            replacer,
+            page_size,
+            num_frames,
+            DEFAULT_PAGE_TABLE_PARTITIONS,
+        )
+    }
+
+    /// with_partitions builds a pool with its page table split in to
+    /// num_partitions hash partitions.
+    pub fn with_partitions(
+        disk_scheduler: DiskScheduler,
+        replacer: impl ReplacementPolicy + 'static,
+        page_size: usize,
+        num_frames: usize,
+        num_partitions: usize,
+    ) -> Result<BufferPoolManager> {
+        if num_partitions == 0 {
+            return Err(Error::InvalidPartitionCount { num_partitions });
+        }
+        if let Some(capacity) = replacer.capacity().filter(|&c| c < num_frames) {
+            return Err(Error::ReplacerTooSmall {
+                capacity,
+                num_frames,
+            });
+        }
+
+        // Pages already in the data file stay addressable after a restart
+        let current_page_index = AtomicUsize::new(disk_scheduler.page_count()?);
+        let frames = (0..num_frames)
+            .map(|_| Arc::new(RwLock::new(Frame::new(page_size))))
+            .collect();
+        let free_list = (0..num_frames).collect();
+
+        Ok(BufferPoolManager {
+            disk_scheduler: Arc::new(disk_scheduler),
+            partitions: (0..num_partitions).map(|_| Mutex::default()).collect(),
+            free_list: Arc::new(Mutex::new(free_list)),
+            current_page_index,
+            replacer: Arc::new(replacer),
+            frames,
+            latches: (0..num_frames).map(|_| Arc::default()).collect(),
+            page_size,
+            background_writer: Mutex::default(),
+            checkpointer: Mutex::default(),
+        })
+    }
+
+    /// with_policy builds a pool with one of the built in replacement
+    /// policies sized to the pool.
+    pub fn with_policy(
+        disk_scheduler: DiskScheduler,
+        policy: Policy,
+        page_size: usize,
+        num_frames: usize,
+    ) -> Result<BufferPoolManager> {
+        Self::new(
+            disk_scheduler,
+            policy.build(num_frames),
+            page_size,
+            num_frames,
+        )
+    }
+
+    pub fn page_size(&self) -> usize {
+        self.page_size
+    }
+
+    /// new_page creates a new page entry on disk
+    /// reusing a deleted page if there is one, otherwise
+    /// increasing the file size of the page file
+    /// it returns the page_id
+    pub fn new_page(&self) -> Result<usize> {
+        let page_id = self.disk_scheduler.allocate_page()?;
+        self.current_page_index.fetch_max(page_id, Relaxed);
+
+        Ok(page_id)
+    }
+
+    /// track_page makes a page allocated through another pool on the
+    /// same data file addressable in this one.
+    pub(crate) fn track_page(&self, page_id: usize) {
+        self.current_page_index.fetch_max(page_id, Relaxed);
+    }
+
+    /// delete_page drops a page from the buffer pool and
+    /// releases it on disk so new_page can hand it out
+    /// again. Pinned pages are never deleted, and a page still being
+    /// read in is reported as [Error::LockContention].
+    pub fn delete_page(&self, page_id: usize) -> Result<()> {
+        let mut free_list = self.free_list.lock()?;
+        let mut partition = self.partition(page_id).lock()?;
+        if partition.loading.contains_key(&page_id) {
+            return Err(Error::LockContention);
+        }
+
+        if let Some(frame_id) = partition.page_table.get(&page_id).copied() {
+            let mut frame = self.frames[frame_id].write()?;
+            if frame.pin_count.load(Relaxed) > 0 {
+                return Err(Error::PagePinned { page_id });
+            }
+
+            partition.page_table.remove(&page_id);
+            self.replacer.remove(frame_id);
+            frame.buffer.fill(0);
+            frame.current_page_index = None;
+            frame.dirty = false;
+            free_list.push(frame_id);
+        }
+
+        self.disk_scheduler.deallocate_page(page_id)
+    }
+
+    /// read_page pins a page and latches it for reading, waiting for
+    /// a writer to finish first.
+    pub fn read_page(&self, page_id: usize) -> Result<ReadPage> {
+        let frame_id = self.check_page(page_id, None)?;
+        self.read_guard(page_id, frame_id, true)
+    }
+
+    /// write_page pins a page and latches it for writing, waiting for
+    /// the readers and any other writer to finish first.
+    pub fn write_page(&self, page_id: usize) -> Result<WritePage> {
+        let frame_id = self.check_page(page_id, None)?;
+        self.write_guard(page_id, frame_id, true)
+    }
+
+    /// try_read_page is [BufferPoolManager::read_page] that returns
+    /// [Error::LockContention] instead of waiting when a writer holds
+    /// the page. A miss still reads the page in first.
+    pub fn try_read_page(&self, page_id: usize) -> Result<ReadPage> {
+        let frame_id = self.check_page(page_id, None)?;
+        self.read_guard(page_id, frame_id, false)
+    }
+
+    /// try_write_page is [BufferPoolManager::write_page] that returns
+    /// [Error::LockContention] instead of waiting when the page is
+    /// held by anyone else.
+    pub fn try_write_page(&self, page_id: usize) -> Result<WritePage> {
+        let frame_id = self.check_page(page_id, None)?;
+        self.write_guard(page_id, frame_id, false)
+    }
+
+    /// read_page_with reads a page through an access strategy, so a
+    /// miss recycles a frame from the strategy's ring instead of
+    /// evicting one from the rest of the pool.
+    pub fn read_page_with(
+        &self,
+        page_id: usize,
+        strategy: &BufferAccessStrategy,
+    ) -> Result<ReadPage> {
+        let frame_id = self.check_page(page_id, Some(strategy))?;
+        self.read_guard(page_id, frame_id, true)
+    }
+
+    /// write_page_with is [BufferPoolManager::read_page_with] for
+    /// writes.
+    pub fn write_page_with(
+        &self,
+        page_id: usize,
+        strategy: &BufferAccessStrategy,
+    ) -> Result<WritePage> {
+        let frame_id = self.check_page(page_id, Some(strategy))?;
+        self.write_guard(page_id, frame_id, true)
+    }
+
+    /// access_strategy builds a [BufferAccessStrategy] with a ring
+    /// sized for this pool.
+    pub fn access_strategy(&self, kind: AccessStrategy) -> BufferAccessStrategy {
+        BufferAccessStrategy::new(kind, self.page_size, self.frames.len())
+    }
+
+    /// read_page_async is [BufferPoolManager::read_page] for async
+    /// callers. On a miss the task waits on the disk rather than
+    /// blocking its thread.
+    pub async fn read_page_async(&self, page_id: usize) -> Result<ReadPage> {
+        let frame_id = self.check_page_async(page_id, None).await?;
+        self.read_guard(page_id, frame_id, true)
+    }
+
+    /// write_page_async is [BufferPoolManager::write_page] for async
+    /// callers.
+    pub async fn write_page_async(&self, page_id: usize) -> Result<WritePage> {
+        let frame_id = self.check_page_async(page_id, None).await?;
+        self.write_guard(page_id, frame_id, true)
+    }
+
+    fn pin(&self, frame: &Frame, frame_id: usize) {
+        pin(frame, frame_id, &*self.replacer)
+    }
+
+    fn read_guard(&self, page_id: usize, frame_id: usize, wait: bool) -> Result<ReadPage> {
+        Ok(ReadPage {
+            page_id,
+            pinned: AtomicUsize::new(1),
+            frame: Arc::clone(&self.frames[frame_id]),
+            pin: Some(self.latch(frame_id, false, wait)?),
+            position: 0,
+        })
+    }
+
+    fn write_guard(&self, page_id: usize, frame_id: usize, wait: bool) -> Result<WritePage> {
+        Ok(WritePage {
+            page_id,
+            pinned: AtomicUsize::new(1),
+            frame: Arc::clone(&self.frames[frame_id]),
+            pin: Some(self.latch(frame_id, true, wait)?),
+            position: 0,
+        })
+    }
+
+    /// latch takes the page latch of a frame that has just been
+    /// pinned. Without wait it gives up straight away if the latch is
+    /// held, letting go of the pin and returning
+    /// [Error::LockContention].
+    fn latch(&self, frame_id: usize, exclusive: bool, wait: bool) -> Result<FramePin> {
+        let latch = &self.latches[frame_id];
+        let latched = match (exclusive, wait) {
+            (false, true) => {
+                latch.lock_shared();
+                true
+            }
+            (true, true) => {
+                latch.lock_exclusive();
+                true
+            }
+            (false, false) => latch.try_lock_shared(),
+            (true, false) => latch.try_lock_exclusive(),
+        };
+        if !latched {
+            unpin(&self.frames[frame_id], frame_id, &*self.replacer);
+            return Err(Error::LockContention);
+        }
+
+        Ok(FramePin {
+            frame_id,
+            frame: Arc::clone(&self.frames[frame_id]),
+            replacer: Arc::clone(&self.replacer),
+            disk_scheduler: Arc::clone(&self.disk_scheduler),
+            latch: Arc::clone(latch),
+            exclusive,
+        })
+    }
+
+    /// start_background_writer starts a thread that writes dirty,
+    /// unpinned frames just ahead of the replacer's clock hand, so
+    /// the sweep mostly finds clean victims and misses rarely wait on
+    /// a write. Policies without a hand have their frames scanned in
+    /// turn instead. A writer already running is replaced.
+    pub fn start_background_writer(&self, config: BackgroundWriterConfig) -> Result<()> {
+        let mut cleaner = self.frame_cleaner(config);
+        let writer = BackgroundWorker::spawn(config.delay, move |_| {
+            cleaner.round();
+        });
+        let previous = self.background_writer.lock()?.replace(writer);
+        drop(previous);
+
+        Ok(())
+    }
+
+    pub fn stop_background_writer(&self) -> Result<()> {
+        let writer = self.background_writer.lock()?.take();
+        drop(writer);
+
+        Ok(())
+    }
+
+    fn frame_cleaner(&self, config: BackgroundWriterConfig) -> FrameCleaner {
+        FrameCleaner {
+            writer: self.frame_writer(),
+            config,
+            cursor: 0,
+        }
+    }
+
+    /// start_checkpointer starts a thread that takes a checkpoint
+    /// every interval, spreading its writes so they don't hold up
+    /// the foreground. A checkpointer already running is replaced.
+    pub fn start_checkpointer(&self, config: CheckpointConfig) -> Result<()> {
+        let writer = self.frame_writer();
+        let checkpointer = BackgroundWorker::spawn(config.interval, move |signal| {
+            match writer.checkpoint(Some((config.spread, signal))) {
+                Ok(_) | Err(Error::Cancelled) => {}
+                Err(err) => eprintln!("Checkpoint failed: {}", err),
+            }
+        });
+        let previous = self.checkpointer.lock()?.replace(checkpointer);
+        drop(previous);
+
+        Ok(())
+    }
+
+    /// stop_checkpointer stops the checkpointer, abandoning a
+    /// checkpoint in progress.
+    pub fn stop_checkpointer(&self) -> Result<()> {
+        let checkpointer = self.checkpointer.lock()?.take();
+        drop(checkpointer);
+
+        Ok(())
+    }
+
+    /// checkpoint_now takes a checkpoint straight away without spacing
+    /// out its writes, and returns its number. Every page dirty when
+    /// it starts is on disk once the checkpoint is recorded in the
+    /// data file header. Like [BufferPoolManager::flush_all] it waits
+    /// for writers to finish.
+    pub fn checkpoint_now(&self) -> Result<u64> {
+        self.frame_writer().checkpoint(None)
+    }
+
+    fn frame_writer(&self) -> FrameWriter {
+        FrameWriter {
+            partitions: Arc::clone(&self.partitions),
+            frames: Arc::clone(&self.frames),
+            latches: Arc::clone(&self.latches),
+            replacer: Arc::clone(&self.replacer),
+            disk_scheduler: Arc::clone(&self.disk_scheduler),
+        }
+    }
+
+    /// shutdown writes every dirty frame back to disk and then shuts
+    /// down the [DiskScheduler], which drains its queue and syncs the
+    /// data file. Pages can no longer be read in afterwards. It never
+    /// waits on a guard, a dirty page held by a writer is left out and
+    /// reported with [Error::LockContention].
+    pub fn shutdown(&self) -> Result<()> {
+        self.stop_background_writer()?;
+        self.stop_checkpointer()?;
+        let flushed = self.flush_dirty_frames(false);
+        let shutdown = self.disk_scheduler.shutdown();
+        flushed.and(shutdown)
+    }
+
+    /// flush_page writes page_id to disk if it is resident and dirty,
+    /// then syncs the data file. It waits for a writer of the page to
+    /// finish, so a thread holding the page's [WritePage] should call
+    /// [WritePage::flush] instead.
+    pub fn flush_page(&self, page_id: usize) -> Result<()> {
+        self.check_range(page_id)?;
+        self.frame_writer()
+            .flush_page(page_id, IoPriority::ForegroundWrite, true)?;
+        self.disk_scheduler.sync()
+    }
+
+    /// flush_all writes every dirty frame to disk and then syncs the
+    /// data file. Like [BufferPoolManager::flush_page] it waits for
+    /// writers to finish.
+    pub fn flush_all(&self) -> Result<()> {
+        self.flush_dirty_frames(true)?;
+        self.disk_scheduler.sync()
+    }
+
+    /// flush_dirty_frames writes back every dirty frame, pinned or
+    /// not, waiting for writers if wait is set. It keeps going past a
+    /// failed frame and returns the first error.
+    fn flush_dirty_frames(&self, wait: bool) -> Result<()> {
+        let writer = self.frame_writer();
+        (0..self.frames.len())
+            .map(|frame_id| {
+                writer
+                    .flush(frame_id, None, IoPriority::ForegroundWrite, wait)
+                    .map(drop)
+            })
+            .fold(Ok(()), Result::and)
+    }
+
+    /// check_page checks if the requests page
+    /// is already mapped to a frame. If it is not
+    /// a frame is taken from the free list, or evicted
+    /// by the replacer, and the page is read in to it
+    /// from disk. Dirty victims are written back first.
+    /// Concurrent misses on a page share a single read.
+    fn check_page(&self, page_id: usize, strategy: Option<&BufferAccessStrategy>) -> Result<usize> {
+        loop {
+            let mut load = match self.begin_load(page_id, strategy)? {
+                Lookup::Hit(frame_id) => return Ok(frame_id),
+                Lookup::Wait(in_flight) => {
+                    in_flight.wait()?;
+                    continue;
+                }
+                Lookup::Miss(load) => load,
+            };
+
+            if let Some(victim) = load.victim {
+                self.wait_io(true, load.data.clone(), victim)?;
+                load.victim_written = true;
+            }
+            self.wait_io(false, load.data.clone(), page_id)?;
+
+            return load.finish();
+        }
+    }
+
+    /// check_page_async is check_page for async callers. No lock is
+    /// held across an await, so the future can be sent between
+    /// threads.
+    async fn check_page_async(
+        &self,
+        page_id: usize,
+        strategy: Option<&BufferAccessStrategy>,
+    ) -> Result<usize> {
+        loop {
+            let mut load = match self.begin_load(page_id, strategy)? {
+                Lookup::Hit(frame_id) => return Ok(frame_id),
+                Lookup::Wait(in_flight) => {
+                    LoadWait(in_flight).await?;
+                    continue;
+                }
+                Lookup::Miss(load) => load,
+            };
+
+            if let Some(victim) = load.victim {
+                self.disk_scheduler
+                    .submit(IoPriority::ForegroundWrite, true, load.data.clone(), victim)?
+                    .await?;
+                load.victim_written = true;
+            }
+            self.disk_scheduler
+                .submit(
+                    IoPriority::ForegroundRead,
+                    false,
+                    load.data.clone(),
+                    page_id,
+                )?
+                .await?;
+
+            return load.finish();
+        }
+    }
+
+    /// begin_load resolves a hit straight away, locking only the
+    /// page's partition. On a miss it claims a frame and unmaps its
+    /// old page, marking both pages as loading.
+    fn begin_load(
+        &self,
+        page_id: usize,
+        strategy: Option<&BufferAccessStrategy>,
+    ) -> Result<Lookup<'_>> {
+        self.check_range(page_id)?;
+
+        loop {
+            {
+                let partition = self.partition(page_id).lock()?;
+                if let Some(in_flight) = partition.loading.get(&page_id) {
+                    return Ok(Lookup::Wait(in_flight.clone()));
+                }
+
+                // The page is pinned before its partition is let go,
+                // so it can't be evicted before the caller gets its
+                // guard. The replacer's lock, if it has one, is the
+                // only lock hits on different pages share
+                if let Some(frame_id) = partition.page_table.get(&page_id).copied() {
+                    self.replacer.record_access(frame_id, page_id);
+                    self.pin(&*self.frames[frame_id].write()?, frame_id);
+                    return Ok(Lookup::Hit(frame_id));
+                }
+            }
+
+            if let Some(load) = self.claim_frame(page_id, strategy)? {
+                return Ok(Lookup::Miss(load));
+            }
+        }
+    }
+
+    /// claim_frame takes a frame for page_id from the strategy's ring,
+    /// the free list or the replacer, in that order. The partitions
+    /// are only locked once the frame and its old page are known, so
+    /// in the meantime a hit may pin the old page or another miss may
+    /// load page_id. The frame is handed back and None returned for
+    /// the lookup to be tried again.
+    ///
+    /// Deleted pages and free map overflow pages are turned away here
+    /// rather than on every hit, a resident page is always allocated
+    /// and delete_page holds the free list while it releases a page.
+    fn claim_frame(
+        &self,
+        page_id: usize,
+        strategy: Option<&BufferAccessStrategy>,
+    ) -> Result<Option<PageLoad<'_>>> {
+        let mut free_list = self.free_list.lock()?;
+        if !self.disk_scheduler.is_allocated(page_id) {
+            return Err(Error::PageNotAllocated { page_id });
+        }
+        let frame_id = match strategy.and_then(BufferAccessStrategy::next) {
+            Some((frame_id, ring_page_id)) if self.take_ring_frame(frame_id, ring_page_id)? => {
+                frame_id
+            }
+            _ => match free_list.pop() {
+                Some(frame_id) => frame_id,
+                None => self.replacer.evict()?,
+            },
+        };
+        let old_page_id = self.frames[frame_id].read()?.current_page_index;
+
+        let mut partitions = self.lock_partitions(&[Some(page_id), old_page_id])?;
+        let mut frame = self.frames[frame_id].write()?;
+        let pinned = frame.pin_count.load(Relaxed) > 0;
+        if pinned || partitions.get(page_id).contains(page_id) {
+            match old_page_id {
+                Some(old_page_id) => {
+                    self.replacer.record_access(frame_id, old_page_id);
+                    if pinned {
+                        self.replacer.set_evictable(frame_id, false);
+                    }
+                }
+                None => {
+                    drop(frame);
+                    free_list.push(frame_id);
+                }
+            }
+            return Ok(None);
+        }
+
+        if let Some(strategy) = strategy {
+            strategy.put(frame_id, page_id);
+        }
+
+        let victim = match frame.current_page_index.take() {
+            Some(old_page_id) => {
+                partitions.get(old_page_id).page_table.remove(&old_page_id);
+                frame.dirty.then_some(old_page_id)
+            }
+            None => None,
+        };
+        frame.dirty = false;
+
+        let in_flight = Arc::new(InFlightLoad::default());
+        partitions
+            .get(page_id)
+            .loading
+            .insert(page_id, in_flight.clone());
+        if let Some(victim) = victim {
+            partitions
+                .get(victim)
+                .loading
+                .insert(victim, in_flight.clone());
+        }
+
+        Ok(Some(PageLoad {
+            buffer_pool_manager: self,
+            frame_id,
+            page_id,
+            victim,
+            victim_written: false,
+            data: Arc::new(Mutex::new(frame.buffer.clone())),
+            in_flight,
+            finished: false,
+        }))
+    }
+
+    /// take_ring_frame takes a frame back from the replacer for a
+    /// strategy's ring, if it still holds the page the ring put there
+    /// and isn't pinned.
+    fn take_ring_frame(&self, frame_id: usize, ring_page_id: usize) -> Result<bool> {
+        let partition = self.partition(ring_page_id).lock()?;
+        if partition.page_table.get(&ring_page_id) != Some(&frame_id) {
+            return Ok(false);
+        }
+        if self.frames[frame_id].read()?.pin_count.load(Relaxed) > 0 {
+            return Ok(false);
+        }
+
+        self.replacer.remove(frame_id);
+        Ok(true)
+    }
+
+    fn partition(&self, page_id: usize) -> &Mutex<Partition> {
+        &self.partitions[partition_index(page_id, self.partitions.len())]
+    }
+
+    fn lock_partitions(&self, page_ids: &[Option<usize>]) -> Result<LockedPartitions<'_>> {
+        let count = self.partitions.len();
+        let mut indexes = page_ids
+            .iter()
+            .flatten()
+            .map(|&page_id| partition_index(page_id, count))
+            .collect::<Vec<_>>();
+        indexes.sort_unstable();
+        indexes.dedup();
+
+        let guards = indexes
+            .into_iter()
+            .map(|index| Ok((index, self.partitions[index].lock()?)))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(LockedPartitions { guards, count })
+    }
+
+    fn check_range(&self, page_id: usize) -> Result<()> {
+        if page_id == 0 || page_id > self.current_page_index.load(Relaxed) {
+            return Err(Error::PageOutOfRange { page_id });
+        }
+
+        Ok(())
+    }
+
+    fn wait_io(&self, is_write: bool, data: Arc<Mutex<Vec<u8>>>, page_id: usize) -> Result<()> {
+        let (tx, rx) = mpsc::channel();
+        self.disk_scheduler.request(is_write, data, page_id, tx)?;
+        rx.recv().map_err(|_| Error::Disconnected)?
+    }
+}
+
+/// [FrameWriter] writes dirty frames back to disk. It shares the
+/// pool's frames, so background threads can have one of their own.
+struct FrameWriter {
+    partitions: Arc<[Mutex<Partition>]>,
+    frames: Arc<[Arc<RwLock<Frame>>]>,
+    latches: Arc<[Arc<PageLatch>]>,
+    replacer: Arc<dyn ReplacementPolicy>,
+    disk_scheduler: Arc<DiskScheduler>,
+}
+
+impl FrameWriter {
+    /// flush writes a frame back if it is dirty, and holds page_id
+    /// when one is given, returning whether it did. A writer of the
+    /// page has to finish first, or without wait a dirty frame that
+    /// is latched fails with [Error::LockContention].
+    fn flush(
+        &self,
+        frame_id: usize,
+        page_id: Option<usize>,
+        priority: IoPriority,
+        wait: bool,
+    ) -> Result<bool> {
+        let latch = &self.latches[frame_id];
+        if wait {
+            latch.lock_shared();
+        } else if !latch.try_lock_shared() {
+            return match self.frames[frame_id].read()?.dirty {
+                true => Err(Error::LockContention),
+                false => Ok(false),
+            };
+        }
+
+        self.write_back(frame_id, priority, |frame| take_dirty(frame, page_id))
+    }
+
+    /// flush_page is [FrameWriter::flush] for whichever frame holds
+    /// page_id. A page that is loading, or being written back as a
+    /// victim, is waited for first: a victim's only copy is in its
+    /// write, and a failed write puts it back in a frame.
+    fn flush_page(&self, page_id: usize, priority: IoPriority, wait: bool) -> Result<bool> {
+        loop {
+            let (frame_id, in_flight) = {
+                let index = partition_index(page_id, self.partitions.len());
+                let partition = self.partitions[index].lock()?;
+                let in_flight = partition.loading.get(&page_id).cloned();
+                (partition.page_table.get(&page_id).copied(), in_flight)
+            };
+
+            match (frame_id, in_flight) {
+                (_, Some(in_flight)) => in_flight.wait()?,
+                (Some(frame_id), None) => {
+                    return self.flush(frame_id, Some(page_id), priority, wait)
+                }
+                (None, None) => return Ok(false),
+            }
+        }
+    }
+
+    /// clean is flush at background priority for frames nobody is
+    /// using. A frame that is pinned or latched by a writer is left
+    /// alone, as it is about to change again.
+    fn clean(&self, frame_id: usize) -> Result<bool> {
+        if !self.latches[frame_id].try_lock_shared() {
+            return Ok(false);
+        }
+
+        self.write_back(frame_id, IoPriority::BackgroundFlush, |frame| {
+            match frame.pin_count.load(Relaxed) {
+                0 => take_dirty(frame, None),
+                _ => None,
+            }
+        })
+    }
+
+    /// write_back finishes a flush under the shared latch the caller
+    /// took. The page is copied and its write queued before the latch
+    /// is let go, and readers can carry on while it is on its way to
+    /// disk. The frame is pinned until the write is done so its page
+    /// can't be read back in stale.
+    fn write_back(
+        &self,
+        frame_id: usize,
+        priority: IoPriority,
+        take: impl FnOnce(&mut Frame) -> Option<(usize, Vec<u8>)>,
+    ) -> Result<bool> {
+        let frame = &self.frames[frame_id];
+        let copy = frame.write().map(|mut frame| {
+            let copy = take(&mut frame)?;
+            pin(&frame, frame_id, &*self.replacer);
+            Some(copy)
+        });
+        let write_back = copy
+            .map(|copy| copy.map(|copy| WriteBack::queue(&self.disk_scheduler, priority, copy)));
+        self.latches[frame_id].unlock_shared();
+
+        let Some(write_back) = write_back? else {
+            return Ok(false);
+        };
+        let result = write_back.wait(frame);
+        unpin(frame, frame_id, &*self.replacer);
+
+        result.map(|()| true)
+    }
+
+    /// checkpoint writes out every page that is dirty when it starts,
+    /// then records the checkpoint in the data file header and returns
+    /// its number. Pages dirtied along the way are left for the next
+    /// checkpoint. A page evicted dirty, before or during the
+    /// checkpoint, is waited for until its write lands. Nothing is
+    /// recorded if a write failed.
+    ///
+    /// In the background the writes are spaced evenly over spread,
+    /// a page held by a writer is come back to later rather than
+    /// waited on, and stopping the worker abandons the checkpoint
+    /// with [Error::Cancelled].
+    fn checkpoint(&self, background: Option<(Duration, &StopSignal)>) -> Result<u64> {
+        let mut dirty = VecDeque::new();
+        for frame in self.frames.iter() {
+            let frame = frame.read()?;
+            if let Some(page_id) = frame.current_page_index.filter(|_| frame.dirty) {
+                dirty.push_back(page_id);
+            }
+        }
+
+        // A dirty page evicted before the scan is only in its victim
+        // write. The partitions are looked at after the frames so a
+        // page can't slip between the two
+        let mut pending = HashSet::new();
+        for partition in self.partitions.iter() {
+            pending.extend(partition.lock()?.loading.keys().copied());
+        }
+        for page_id in &dirty {
+            pending.remove(page_id);
+        }
+        dirty.extend(pending);
+
+        let (priority, wait) = match background {
+            Some(_) => (IoPriority::BackgroundFlush, false),
+            None => (IoPriority::ForegroundWrite, true),
+        };
+        let delay = background.map_or(Duration::ZERO, |(spread, _)| {
+            spread / dirty.len().max(1) as u32
+        });
+        let mut result = Ok(());
+        while let Some(page_id) = dirty.pop_front() {
+            // Coming back to a held page doesn't spin on it
+            let pause = match self.flush_page(page_id, priority, wait) {
+                Err(Error::LockContention) => {
+                    dirty.push_back(page_id);
+                    delay.max(Duration::from_millis(1))
+                }
+                flushed => {
+                    result = result.and(flushed.map(drop));
+                    delay
+                }
+            };
+            if let Some((_, signal)) = background {
+                if !signal.sleep(pause) {
+                    return Err(Error::Cancelled);
+                }
+            }
+        }
+
+        result?;
+        self.disk_scheduler.record_checkpoint()
+    }
+}
+
+/// [FrameCleaner] is the background writer's state between rounds.
+struct FrameCleaner {
+    writer: FrameWriter,
+    config: BackgroundWriterConfig,
+    /// cursor is where the next round starts for policies without a
+    /// clock hand
+    cursor: usize,
+}
+
+impl FrameCleaner {
+    /// round writes up to max_pages of the frames the replacer will
+    /// look at next and returns how many it wrote.
+    fn round(&mut self) -> usize {
+        let frames = self.writer.frames.len();
+        let lookahead = self.config.lookahead.min(frames);
+        let candidates = self
+            .writer
+            .replacer
+            .ahead_of_hand(lookahead)
+            .unwrap_or_else(|| {
+                let start = self.cursor;
+                self.cursor = (start + lookahead) % frames.max(1);
+                (start..start + lookahead)
+                    .map(|frame_id| frame_id % frames)
+                    .collect()
+            });
+
+        let mut written = 0;
+        for frame_id in candidates {
+            if written == self.config.max_pages {
+                break;
+            }
+            if let Ok(true) = self.writer.clean(frame_id) {
+                written += 1;
+            }
+        }
+
+        written
+    }
+}
+
+impl Drop for BufferPoolManager {
+    fn drop(&mut self) {
+        if let Err(err) = self.shutdown() {
+            eprintln!("Error occurred during drop: {}", err);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock_replacer::Replacer;
+    use crate::disk_manager::DiskManager;
+    use crate::io_future::block_on;
+    use crate::DEFAULT_PAGE_SIZE;
+    use std::sync::Arc;
+    use std::thread;
+
+    impl BufferPoolManager {
+        fn frame_of(&self, page_id: usize) -> Option<usize> {
+            let partition = self.partition(page_id).lock().unwrap();
+            partition.page_table.get(&page_id).copied()
+        }
+
+        fn resident_pages(&self) -> usize {
+            self.partitions
+                .iter()
+                .map(|partition| partition.lock().unwrap().page_table.len())
+                .sum()
+        }
+
+        fn loading_pages(&self) -> usize {
+            self.partitions
+                .iter()
+                .map(|partition| partition.lock().unwrap().loading.len())
+                .sum()
+        }
+    }
+
+    #[test]
+    fn new_buffer_pool_manager() {
+        let disk_manager = Arc::new(DiskManager::default());
+        let disk_scheduler = DiskScheduler::new(disk_manager);
+        let replacer = Replacer::<ReplacerNode>::new(10);
+        let buffer_pool_manager =
+            BufferPoolManager::new(disk_scheduler, replacer, DEFAULT_PAGE_SIZE, 10).unwrap();
+
+        assert_eq!(buffer_pool_manager.frames.len(), 10);
+    }
+
+    #[test]
+    fn add_pages() {
+        let disk_manager = Arc::new(DiskManager::default());
+        let disk_scheduler = DiskScheduler::new(disk_manager);
+        let replacer = Replacer::<ReplacerNode>::new(10);
+        let buffer_pool_manager =
+            BufferPoolManager::new(disk_scheduler, replacer, DEFAULT_PAGE_SIZE, 10).unwrap();
+
+        assert_eq!(buffer_pool_manager.frames.len(), 10);
+
+        // Create 10 new pages in memory for this test
+        // return the page index.
+        for i in 1..11 {
+            let np = buffer_pool_manager.new_page().unwrap();
+            assert_eq!(np, i);
+        }
+    }
+
+    #[test]
+    fn test_page_guard_seek() {
+        let frame = Arc::new(RwLock::new(Frame::new(5)));
+        let mut wp = WritePage::new(1, Default::default(), Arc::clone(&frame));
+
+        // Zeros in the page don't move the write position
+        assert_eq!(wp.write(&[1, 0, 3]).unwrap(), 3);
+        assert_eq!(wp.write(&[0]).unwrap(), 1);
+        assert_eq!(wp.write(&[5, 6]).unwrap(), 1);
+        assert_eq!(wp.write(&[7]).unwrap(), 0);
+        assert_eq!(&*wp, &[1, 0, 3, 0, 5]);
+
+        assert_eq!(wp.seek(SeekFrom::End(-2)).unwrap(), 3);
+        wp.write_all(&[4]).unwrap();
+        wp[0] = 9;
+        assert!(wp.seek(SeekFrom::Current(-5)).is_err());
+        assert_eq!(wp.seek(SeekFrom::Current(10)).unwrap(), 14);
+        assert!(wp.write_all(&[1]).is_err());
+
+        // Writing nothing leaves a clean page clean
+        frame.write().unwrap().dirty = false;
+        assert_eq!(wp.write(&[]).unwrap(), 0);
+        assert_eq!(wp.write(&[1]).unwrap(), 0);
+        assert!(!wp.is_dirty().unwrap());
+        drop(wp);
+
+        let mut rp = ReadPage::new(1, Default::default(), frame);
+        assert_eq!(&*rp, &[9, 0, 3, 4, 5]);
+
+        // Buffers of any size read on from the last position
+        let mut buf = [0; 2];
+        rp.read_exact(&mut buf).unwrap();
+        assert_eq!(buf, [9, 0]);
+        rp.seek(SeekFrom::Start(4)).unwrap();
+        assert_eq!(rp.read(&mut buf).unwrap(), 1);
+        assert_eq!(buf, [5, 0]);
+        assert_eq!(rp.read(&mut buf).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_read_write_page_frame() {
+        let frame = Arc::new(RwLock::new(Frame::new(5)));
+        let f = Arc::clone(&frame);
+        assert!(!f.read().unwrap().dirty);
+
+        let mut wp = WritePage::new(1, Default::default(), f);
+
+        let a = wp.write(&[97, 97]).unwrap();
+        assert_eq!(a, 2);
+        let is_dirty = wp.is_dirty().unwrap();
+        assert!(is_dirty);
+
+        drop(wp);
+
+        let mut rp = ReadPage::new(1, Default::default(), frame);
+
+        let mut buf = vec![0; 5];
+        let buf_read = rp.read(&mut buf);
+        assert!(buf_read.is_ok());
+        assert_eq!(buf_read.unwrap(), 5);
+        assert_eq!(buf, vec![97, 97, 0, 0, 0]);
+    }
+
+    #[test]
+    fn test_read_write_page() {
+        let disk_manager = Arc::new(DiskManager::default());
+        let disk_scheduler = DiskScheduler::new(disk_manager);
+        let replacer = Replacer::<ReplacerNode>::new(10);
+        let buffer_pool_manager =
+            BufferPoolManager::new(disk_scheduler, replacer, DEFAULT_PAGE_SIZE, 10).unwrap();
+
+        let np = buffer_pool_manager.new_page().unwrap();
+        assert_eq!(np, 1);
+        let np2 = buffer_pool_manager.new_page().unwrap();
+        assert_eq!(np2, 2);
+        let rp = buffer_pool_manager.read_page(np);
+        assert!(rp.is_ok());
+        let rp2 = buffer_pool_manager.read_page(10);
+        assert!(matches!(rp2, Err(Error::PageOutOfRange { page_id: 10 })));
+
+        // A writer has to wait for the reader to finish
+        assert!(matches!(
+            buffer_pool_manager.try_write_page(np),
+            Err(Error::LockContention)
+        ));
+        drop(rp);
+
+        // Write to a page and then read from it
+        let wp = buffer_pool_manager.write_page(np);
+        assert!(wp.is_ok());
+
+        match wp.unwrap().write(b"foo") {
+            Ok(written) => {
+                assert_eq!(written, 3);
+            }
+            Err(err) => {
+                panic!("failed to write buffer page: {}", err);
+            }
+        }
+
+        let rp = buffer_pool_manager.read_page(np);
+        assert!(rp.is_ok());
+        let mut buf = [0_u8; DEFAULT_PAGE_SIZE];
+
+        match rp.unwrap().read(&mut buf) {
+            Ok(_written) => {}
+            Err(err) => {
+                panic!("failed to write buffer page: {}", err);
+            }
+        }
+
+        assert_eq!(buf[..3], [102, 111, 111]);
+
+        // Writes start at the top of the page unless seeked past it
+        let mut wp = buffer_pool_manager.write_page(np).unwrap();
+        wp.seek(SeekFrom::Start(3)).unwrap();
+
+        match wp.write(b"bar") {
+            Ok(written) => {
+                assert_eq!(written, 3);
+            }
+            Err(err) => {
+                panic!("failed to write buffer page: {}", err);
+            }
+        }
+        drop(wp);
+
+        let rp = buffer_pool_manager.read_page(np);
+        assert!(rp.is_ok());
+        let mut buf = [0_u8; DEFAULT_PAGE_SIZE];
+
+        match rp.unwrap().read(&mut buf) {
+            Ok(_written) => {}
+            Err(err) => {
+                panic!("failed to write buffer page: {}", err);
+            }
         }
 
         assert_eq!(buf[..6], [102, 111, 111, 98, 97, 114]);
     }
 
+    #[test]
+    fn test_working_set_larger_than_pool() {
+        let disk_manager = Arc::new(DiskManager::default());
+        let disk_scheduler = DiskScheduler::new(disk_manager);
+        let replacer = Replacer::<ReplacerNode>::new(3);
+        let buffer_pool_manager =
+            BufferPoolManager::new(disk_scheduler, replacer, DEFAULT_PAGE_SIZE, 3).unwrap();
+
+        let pages = (0..10)
+            .map(|_| buffer_pool_manager.new_page().unwrap())
+            .collect::<Vec<_>>();
+
+        // Every write past the third page forces a dirty victim out
+        for page_id in &pages {
+            let mut wp = buffer_pool_manager.write_page(*page_id).unwrap();
+            wp.write_all(format!("page-{}", page_id).as_bytes())
+                .unwrap();
+        }
+
+        for page_id in pages.iter().rev() {
+            let mut rp = buffer_pool_manager.read_page(*page_id).unwrap();
+            let mut buf = [0_u8; DEFAULT_PAGE_SIZE];
+            rp.read_exact(&mut buf).unwrap();
+
+            let expected = format!("page-{}", page_id);
+            assert_eq!(&buf[..expected.len()], expected.as_bytes());
+        }
+    }
+
+    #[test]
+    fn test_replacement_policies() {
+        let policies = [
+            Policy::Clock,
+            Policy::AtomicClock,
+            Policy::Lru,
+            Policy::LruK(2),
+            Policy::TwoQ,
+            Policy::Arc,
+        ];
+        for policy in policies {
+            let disk_manager = Arc::new(DiskManager::default());
+            let disk_scheduler = DiskScheduler::new(disk_manager);
+            let buffer_pool_manager =
+                BufferPoolManager::with_policy(disk_scheduler, policy, DEFAULT_PAGE_SIZE, 4)
+                    .unwrap();
+
+            let pages = (0..12)
+                .map(|_| buffer_pool_manager.new_page().unwrap())
+                .collect::<Vec<_>>();
+            for page_id in &pages {
+                let mut wp = buffer_pool_manager.write_page(*page_id).unwrap();
+                wp.write_all(format!("page-{}", page_id).as_bytes())
+                    .unwrap();
+            }
+
+            // Revisit pages in a different order to exercise the ghost lists
+            for page_id in pages.iter().rev().chain(pages.iter().step_by(3)) {
+                let mut rp = buffer_pool_manager.read_page(*page_id).unwrap();
+                let mut buf = [0_u8; DEFAULT_PAGE_SIZE];
+                rp.read_exact(&mut buf).unwrap();
+
+                let expected = format!("page-{}", page_id);
+                assert_eq!(&buf[..expected.len()], expected.as_bytes(), "{:?}", policy);
+            }
+        }
+
+        // A replacer with fixed slots has to cover every frame
+        let disk_scheduler = DiskScheduler::new(Arc::new(DiskManager::default()));
+        assert!(matches!(
+            BufferPoolManager::new(
+                disk_scheduler,
+                Policy::AtomicClock.build(3),
+                DEFAULT_PAGE_SIZE,
+                4
+            ),
+            Err(Error::ReplacerTooSmall {
+                capacity: 3,
+                num_frames: 4
+            })
+        ));
+    }
+
+    #[test]
+    fn test_access_strategies() {
+        let disk_manager = Arc::new(DiskManager::default());
+        let disk_scheduler = DiskScheduler::new(disk_manager);
+        let replacer = Replacer::<ReplacerNode>::new(16);
+        let buffer_pool_manager =
+            BufferPoolManager::new(disk_scheduler, replacer, DEFAULT_PAGE_SIZE, 16).unwrap();
+        let pages = (0..64)
+            .map(|_| buffer_pool_manager.new_page().unwrap())
+            .collect::<Vec<_>>();
+        let (hot, cold) = pages.split_at(8);
+        for page_id in hot {
+            drop(buffer_pool_manager.read_page(*page_id).unwrap());
+        }
+
+        // A bulk load cycles through a ring of two frames, writing back
+        // each dirty page as its frame comes round again
+        let strategy = buffer_pool_manager.access_strategy(AccessStrategy::BulkWrite);
+        assert_eq!(strategy.ring_size(), 2);
+        for page_id in cold {
+            let mut wp = buffer_pool_manager
+                .write_page_with(*page_id, &strategy)
+                .unwrap();
+            wp.write_all(format!("page-{}", page_id).as_bytes())
+                .unwrap();
+        }
+
+        let strategy = buffer_pool_manager.access_strategy(AccessStrategy::BulkRead);
+        for page_id in cold {
+            let mut rp = buffer_pool_manager
+                .read_page_with(*page_id, &strategy)
+                .unwrap();
+            let mut buf = [0_u8; DEFAULT_PAGE_SIZE];
+            rp.read_exact(&mut buf).unwrap();
+
+            let expected = format!("page-{}", page_id);
+            assert_eq!(&buf[..expected.len()], expected.as_bytes());
+        }
+
+        // Neither scan touched the working set or took more than its
+        // ring from the free list
+        assert!(hot
+            .iter()
+            .all(|page_id| buffer_pool_manager.frame_of(*page_id).is_some()));
+        assert_eq!(buffer_pool_manager.free_list.lock().unwrap().len(), 4);
+    }
+
+    #[test]
+    fn test_pinned_frames_are_never_evicted() {
+        let policies = [
+            Policy::Clock,
+            Policy::AtomicClock,
+            Policy::Lru,
+            Policy::LruK(2),
+            Policy::TwoQ,
+            Policy::Arc,
+        ];
+        for policy in policies {
+            let disk_manager = Arc::new(DiskManager::default());
+            let disk_scheduler = DiskScheduler::new(disk_manager);
+            let buffer_pool_manager =
+                BufferPoolManager::with_policy(disk_scheduler, policy, DEFAULT_PAGE_SIZE, 3)
+                    .unwrap();
+            let pages = (0..10)
+                .map(|_| buffer_pool_manager.new_page().unwrap())
+                .collect::<Vec<_>>();
+
+            let rp = buffer_pool_manager.read_page(pages[0]).unwrap();
+            let mut wp = buffer_pool_manager.write_page(pages[1]).unwrap();
+            wp.write_all(b"pinned").unwrap();
+            let frames = (
+                buffer_pool_manager.frame_of(pages[0]),
+                buffer_pool_manager.frame_of(pages[1]),
+            );
+
+            // Every other page has to share the one unpinned frame
+            for page_id in pages[2..].iter().chain(pages[2..].iter().rev()) {
+                drop(buffer_pool_manager.read_page(*page_id).unwrap());
+                let frame_0 = buffer_pool_manager.frame_of(pages[0]);
+                let frame_1 = buffer_pool_manager.frame_of(pages[1]);
+                assert_eq!((frame_0, frame_1), frames, "{:?}", policy);
+            }
+
+            // With every frame pinned there is nothing left to evict
+            let last = buffer_pool_manager.read_page(pages[2]).unwrap();
+            let result = buffer_pool_manager.read_page(pages[3]);
+            assert!(matches!(result, Err(Error::NoFreeFrame)), "{:?}", policy);
+
+            // Unpinning a frame makes it a victim again
+            drop(wp);
+            drop(buffer_pool_manager.read_page(pages[3]).unwrap());
+            assert_eq!(buffer_pool_manager.frame_of(pages[1]), None);
+            drop((rp, last));
+        }
+    }
+
+    #[test]
+    fn test_reopen_restores_page_index() {
+        let temp_dir = tempdir::TempDir::new("test_buffer_pool_manager").unwrap();
+        let temp_file = temp_dir.path().join("test.db");
+
+        let disk_manager =
+            DiskManager::new(DEFAULT_PAGE_SIZE, Some(temp_file.clone()), false).unwrap();
+        let disk_scheduler = DiskScheduler::new(Arc::new(disk_manager));
+        let buffer_pool_manager = BufferPoolManager::new(
+            disk_scheduler,
+            Replacer::<ReplacerNode>::new(2),
+            DEFAULT_PAGE_SIZE,
+            2,
+        )
+        .unwrap();
+
+        for _ in 0..3 {
+            let page_id = buffer_pool_manager.new_page().unwrap();
+            let mut wp = buffer_pool_manager.write_page(page_id).unwrap();
+            wp.write_all(format!("page-{}", page_id).as_bytes())
+                .unwrap();
+        }
+
+        // Evicting the remaining dirty pages pushes them to disk
+        let page_id = buffer_pool_manager.new_page().unwrap();
+        buffer_pool_manager.read_page(page_id).unwrap();
+        buffer_pool_manager.read_page(1).unwrap();
+        drop(buffer_pool_manager);
+
+        let disk_manager = DiskManager::open(DEFAULT_PAGE_SIZE, temp_file).unwrap();
+        let disk_scheduler = DiskScheduler::new(Arc::new(disk_manager));
+        let buffer_pool_manager = BufferPoolManager::new(
+            disk_scheduler,
+            Replacer::<ReplacerNode>::new(2),
+            DEFAULT_PAGE_SIZE,
+            2,
+        )
+        .unwrap();
+
+        for page_id in 1..4 {
+            let mut rp = buffer_pool_manager.read_page(page_id).unwrap();
+            let mut buf = [0_u8; DEFAULT_PAGE_SIZE];
+            rp.read_exact(&mut buf).unwrap();
+
+            let expected = format!("page-{}", page_id);
+            assert_eq!(&buf[..expected.len()], expected.as_bytes());
+        }
+        assert_eq!(buffer_pool_manager.new_page().unwrap(), 5);
+
+        temp_dir.close().unwrap();
+    }
+
+    #[test]
+    fn test_shutdown_flushes_dirty_frames() {
+        let temp_dir = tempdir::TempDir::new("test_buffer_pool_manager").unwrap();
+        let temp_file = temp_dir.path().join("test.db");
+
+        let disk_manager =
+            DiskManager::new(DEFAULT_PAGE_SIZE, Some(temp_file.clone()), false).unwrap();
+        let disk_scheduler = DiskScheduler::new(Arc::new(disk_manager));
+        let buffer_pool_manager = BufferPoolManager::new(
+            disk_scheduler,
+            Replacer::<ReplacerNode>::new(4),
+            DEFAULT_PAGE_SIZE,
+            4,
+        )
+        .unwrap();
+
+        // Every page stays resident so nothing is written back by eviction
+        for _ in 0..3 {
+            let page_id = buffer_pool_manager.new_page().unwrap();
+            let mut wp = buffer_pool_manager.write_page(page_id).unwrap();
+            wp.write_all(format!("page-{}", page_id).as_bytes())
+                .unwrap();
+        }
+        // Pinned frames are flushed too, as long as no writer holds them
+        let rp = buffer_pool_manager.read_page(1).unwrap();
+
+        buffer_pool_manager.shutdown().unwrap();
+        assert!(!rp.is_dirty().unwrap());
+        drop(rp);
+
+        let page_id = buffer_pool_manager.new_page().unwrap();
+        assert!(matches!(
+            buffer_pool_manager.read_page(page_id),
+            Err(Error::Disconnected)
+        ));
+        drop(buffer_pool_manager);
+
+        let disk_manager = DiskManager::open(DEFAULT_PAGE_SIZE, temp_file).unwrap();
+        let (tx, rx) = mpsc::channel();
+        for page_id in 1..4 {
+            let mut buf = vec![0_u8; DEFAULT_PAGE_SIZE];
+            disk_manager.read_page(&mut buf, page_id, tx.clone());
+            rx.recv().unwrap().unwrap();
+
+            let expected = format!("page-{}", page_id);
+            assert_eq!(&buf[..expected.len()], expected.as_bytes());
+        }
+
+        temp_dir.close().unwrap();
+    }
+
+    #[test]
+    fn test_flush_page() {
+        let disk_manager = Arc::new(DiskManager::default());
+        let disk_scheduler = DiskScheduler::new(Arc::clone(&disk_manager));
+        let buffer_pool_manager = BufferPoolManager::new(
+            disk_scheduler,
+            Replacer::<ReplacerNode>::new(4),
+            DEFAULT_PAGE_SIZE,
+            4,
+        )
+        .unwrap();
+        let on_disk = |page_id| {
+            let (tx, rx) = mpsc::channel();
+            let mut buf = vec![0_u8; DEFAULT_PAGE_SIZE];
+            disk_manager.read_page(&mut buf, page_id, tx);
+            rx.recv().unwrap().unwrap();
+            buf
+        };
+
+        for _ in 0..3 {
+            let page_id = buffer_pool_manager.new_page().unwrap();
+            let mut wp = buffer_pool_manager.write_page(page_id).unwrap();
+            wp.write_all(format!("page-{}", page_id).as_bytes())
+                .unwrap();
+        }
+        assert!(buffer_pool_manager.flush_page(4).is_err());
+
+        buffer_pool_manager.flush_page(1).unwrap();
+        assert_eq!(&on_disk(1)[..6], b"page-1");
+        assert_eq!(&on_disk(2)[..6], &[0; 6]);
+        assert!(!buffer_pool_manager
+            .read_page(1)
+            .unwrap()
+            .is_dirty()
+            .unwrap());
+        assert!(buffer_pool_manager
+            .read_page(2)
+            .unwrap()
+            .is_dirty()
+            .unwrap());
+
+        // The writer flushes its own page without letting go of it
+        let mut wp = buffer_pool_manager.write_page(2).unwrap();
+        wp.flush().unwrap();
+        assert!(!wp.is_dirty().unwrap());
+        assert_eq!(&on_disk(2)[..6], b"page-2");
+        drop(wp);
+
+        buffer_pool_manager.flush_all().unwrap();
+        assert_eq!(&on_disk(3)[..6], b"page-3");
+        for page_id in 1..4 {
+            let rp = buffer_pool_manager.read_page(page_id).unwrap();
+            assert!(!rp.is_dirty().unwrap());
+        }
+
+        // A failed write is reported and leaves the page dirty
+        let mut wp = buffer_pool_manager.write_page(1).unwrap();
+        wp.write_all(b"again!").unwrap();
+        buffer_pool_manager.disk_scheduler.shutdown().unwrap();
+        assert!(wp.flush().is_err());
+        assert!(wp.is_dirty().unwrap());
+        drop(wp);
+        assert_eq!(&on_disk(1)[..6], b"page-1");
+    }
+
+    #[test]
+    fn test_flush_victim() {
+        // flush_page and checkpoints both have to wait on a victim
+        for checkpoint in [false, true] {
+            let disk_manager = Arc::new(DiskManager::default());
+            let disk_scheduler = DiskScheduler::with_workers(Arc::clone(&disk_manager), 1);
+            let buffer_pool_manager = BufferPoolManager::new(
+                disk_scheduler,
+                Replacer::<ReplacerNode>::new(1),
+                DEFAULT_PAGE_SIZE,
+                1,
+            )
+            .unwrap();
+            for _ in 0..3 {
+                buffer_pool_manager.new_page().unwrap();
+            }
+            buffer_pool_manager
+                .write_page(1)
+                .unwrap()
+                .write_all(b"page-1")
+                .unwrap();
+
+            // Hold the only worker up so evicting page 1 stalls in its write
+            let (call_tx, call_rx) = mpsc::channel();
+            let blocker = Arc::new(Mutex::new(vec![0; DEFAULT_PAGE_SIZE]));
+            let held = blocker.lock().unwrap();
+            let disk_scheduler = &buffer_pool_manager.disk_scheduler;
+            disk_scheduler
+                .request(true, blocker.clone(), 3, call_tx)
+                .unwrap();
+            while disk_scheduler.stats().unwrap().in_flight == 0 {
+                thread::yield_now();
+            }
+
+            thread::scope(|s| {
+                let reader = s.spawn(|| buffer_pool_manager.read_page(2).map(drop));
+                while buffer_pool_manager.frame_of(1).is_some() {
+                    thread::yield_now();
+                }
+
+                // Flushing the victim waits for its write to land
+                let flusher = s.spawn(|| {
+                    match checkpoint {
+                        true => buffer_pool_manager.checkpoint_now().map(drop),
+                        false => buffer_pool_manager.flush_page(1),
+                    }
+                    .unwrap();
+                    let (tx, rx) = mpsc::channel();
+                    let mut buf = vec![0_u8; DEFAULT_PAGE_SIZE];
+                    disk_manager.read_page(&mut buf, 1, tx);
+                    rx.recv().unwrap().unwrap();
+                    buf
+                });
+                thread::sleep(Duration::from_millis(10));
+                drop(held);
+                call_rx.recv().unwrap().unwrap();
+                assert_eq!(&flusher.join().unwrap()[..6], b"page-1");
+                reader.join().unwrap().unwrap();
+            });
+        }
+    }
+
+    #[test]
+    fn test_background_writer() {
+        let disk_manager = Arc::new(DiskManager::default());
+        let on_disk = |page_id| {
+            let (tx, rx) = mpsc::channel();
+            let mut buf = vec![0_u8; DEFAULT_PAGE_SIZE];
+            disk_manager.read_page(&mut buf, page_id, tx);
+            rx.recv().unwrap().unwrap();
+            buf
+        };
+        let config = BackgroundWriterConfig {
+            delay: Duration::from_millis(1),
+            lookahead: 4,
+            max_pages: 1,
+        };
+
+        for policy in [Policy::Clock, Policy::AtomicClock, Policy::Lru] {
+            let disk_scheduler = DiskScheduler::new(Arc::clone(&disk_manager));
+            let buffer_pool_manager =
+                BufferPoolManager::with_policy(disk_scheduler, policy, DEFAULT_PAGE_SIZE, 4)
+                    .unwrap();
+            let pages = (0..3)
+                .map(|_| {
+                    let page_id = buffer_pool_manager.new_page().unwrap();
+                    let mut wp = buffer_pool_manager.write_page(page_id).unwrap();
+                    wp.write_all(format!("page-{}", page_id).as_bytes())
+                        .unwrap();
+                    page_id
+                })
+                .collect::<Vec<_>>();
+
+            // A round writes at most max_pages, and never a pinned page
+            let rp = buffer_pool_manager.read_page(pages[2]).unwrap();
+            let mut cleaner = buffer_pool_manager.frame_cleaner(config);
+            assert_eq!(cleaner.round(), 1, "{:?}", policy);
+            assert_eq!(cleaner.round(), 1, "{:?}", policy);
+            assert_eq!(cleaner.round(), 0, "{:?}", policy);
+            for page_id in &pages[..2] {
+                let expected = format!("page-{}", page_id);
+                assert_eq!(&on_disk(*page_id)[..expected.len()], expected.as_bytes());
+            }
+            assert!(rp.is_dirty().unwrap());
+            drop(rp);
+
+            // The writer thread cleans pages written after it started
+            buffer_pool_manager.start_background_writer(config).unwrap();
+            let page_id = buffer_pool_manager.new_page().unwrap();
+            let mut wp = buffer_pool_manager.write_page(page_id).unwrap();
+            wp.write_all(b"written behind").unwrap();
+            drop(wp);
+
+            let frame_id = buffer_pool_manager.frame_of(page_id).unwrap();
+            let start = std::time::Instant::now();
+            // The frame is marked clean once copied and unpinned once
+            // the copy is written
+            while {
+                let frame = buffer_pool_manager.frames[frame_id].read().unwrap();
+                frame.dirty || frame.pin_count.load(Relaxed) > 0
+            } {
+                assert!(start.elapsed() < Duration::from_secs(10), "{:?}", policy);
+                thread::sleep(Duration::from_millis(1));
+            }
+            assert_eq!(&on_disk(page_id)[..14], b"written behind");
+            buffer_pool_manager.shutdown().unwrap();
+        }
+    }
+
+    #[test]
+    fn test_checkpoint() {
+        let temp_dir = tempdir::TempDir::new("test_buffer_pool_manager").unwrap();
+        let temp_file = temp_dir.path().join("test.db");
+
+        let disk_manager =
+            Arc::new(DiskManager::new(DEFAULT_PAGE_SIZE, Some(temp_file.clone()), false).unwrap());
+        let disk_scheduler = DiskScheduler::new(Arc::clone(&disk_manager));
+        let buffer_pool_manager =
+            BufferPoolManager::with_policy(disk_scheduler, Policy::Clock, DEFAULT_PAGE_SIZE, 4)
+                .unwrap();
+        let on_disk = |page_id| {
+            let (tx, rx) = mpsc::channel();
+            let mut buf = vec![0_u8; DEFAULT_PAGE_SIZE];
+            disk_manager.read_page(&mut buf, page_id, tx);
+            rx.recv().unwrap().unwrap();
+            buf
+        };
+
+        for _ in 0..3 {
+            let page_id = buffer_pool_manager.new_page().unwrap();
+            let mut wp = buffer_pool_manager.write_page(page_id).unwrap();
+            wp.write_all(format!("page-{}", page_id).as_bytes())
+                .unwrap();
+        }
+
+        // A reader doesn't hold the checkpoint up
+        let rp = buffer_pool_manager.read_page(2).unwrap();
+        assert_eq!(buffer_pool_manager.checkpoint_now().unwrap(), 1);
+        assert_eq!(disk_manager.last_checkpoint(), 1);
+        assert!(!rp.is_dirty().unwrap());
+        drop(rp);
+        for page_id in 1..4 {
+            let expected = format!("page-{}", page_id);
+            assert_eq!(&on_disk(page_id)[..6], expected.as_bytes());
+        }
+
+        // The checkpointer picks up pages dirtied since
+        let mut wp = buffer_pool_manager.write_page(3).unwrap();
+        wp.write_all(b"again!").unwrap();
+        drop(wp);
+        buffer_pool_manager
+            .start_checkpointer(CheckpointConfig {
+                interval: Duration::from_millis(1),
+                spread: Duration::from_millis(5),
+            })
+            .unwrap();
+        let start = std::time::Instant::now();
+        while disk_manager.last_checkpoint() < 2 {
+            assert!(start.elapsed() < Duration::from_secs(10));
+            thread::sleep(Duration::from_millis(1));
+        }
+        buffer_pool_manager.stop_checkpointer().unwrap();
+        assert_eq!(&on_disk(3)[..6], b"again!");
+
+        let checkpoint = disk_manager.last_checkpoint();
+        drop(buffer_pool_manager);
+        drop(disk_manager);
+        let disk_manager = DiskManager::open(DEFAULT_PAGE_SIZE, temp_file).unwrap();
+        assert_eq!(disk_manager.last_checkpoint(), checkpoint);
+
+        temp_dir.close().unwrap();
+    }
+
+    #[test]
+    fn test_shutdown_with_page_held() {
+        let disk_manager = Arc::new(DiskManager::default());
+        let disk_scheduler = DiskScheduler::new(disk_manager);
+        let buffer_pool_manager = BufferPoolManager::new(
+            disk_scheduler,
+            Replacer::<ReplacerNode>::new(4),
+            DEFAULT_PAGE_SIZE,
+            4,
+        )
+        .unwrap();
+        buffer_pool_manager
+            .start_checkpointer(CheckpointConfig {
+                interval: Duration::from_millis(1),
+                spread: Duration::from_millis(1),
+            })
+            .unwrap();
+
+        for _ in 0..2 {
+            buffer_pool_manager.new_page().unwrap();
+        }
+        let mut wp = buffer_pool_manager.write_page(1).unwrap();
+        wp.write_all(b"held").unwrap();
+        buffer_pool_manager
+            .write_page(2)
+            .unwrap()
+            .write_all(b"let go")
+            .unwrap();
+
+        // Shutdown skips the held page rather than waiting for it, and
+        // so does dropping the pool
+        assert!(matches!(
+            buffer_pool_manager.shutdown(),
+            Err(Error::LockContention)
+        ));
+        assert!(wp.is_dirty().unwrap());
+        let frame_id = buffer_pool_manager.frame_of(2).unwrap();
+        assert!(!buffer_pool_manager.frames[frame_id].read().unwrap().dirty);
+        drop(buffer_pool_manager);
+        drop(wp);
+    }
+
+    #[test]
+    fn test_read_write_page_async() {
+        let disk_manager = Arc::new(DiskManager::default());
+        let disk_scheduler = DiskScheduler::new(disk_manager);
+        let buffer_pool_manager = BufferPoolManager::new(
+            disk_scheduler,
+            Replacer::<ReplacerNode>::new(2),
+            DEFAULT_PAGE_SIZE,
+            2,
+        )
+        .unwrap();
+        for _ in 0..4 {
+            buffer_pool_manager.new_page().unwrap();
+        }
+
+        fn assert_send<T: Send>(_: &T) {}
+        assert_send(&buffer_pool_manager.read_page_async(1));
+
+        // Cycling four pages through two frames writes back dirty victims
+        for page_id in 1..5 {
+            let mut wp = block_on(buffer_pool_manager.write_page_async(page_id)).unwrap();
+            wp.write_all(format!("page-{}", page_id).as_bytes())
+                .unwrap();
+        }
+        for page_id in 1..5 {
+            let mut rp = block_on(buffer_pool_manager.read_page_async(page_id)).unwrap();
+            let mut buf = [0_u8; DEFAULT_PAGE_SIZE];
+            rp.read_exact(&mut buf).unwrap();
+
+            let expected = format!("page-{}", page_id);
+            assert_eq!(&buf[..expected.len()], expected.as_bytes());
+        }
+
+        assert!(matches!(
+            block_on(buffer_pool_manager.read_page_async(9)),
+            Err(Error::PageOutOfRange { page_id: 9 })
+        ));
+    }
+
+    #[test]
+    fn test_dropped_page_load() {
+        let disk_manager = Arc::new(DiskManager::default());
+        let disk_scheduler = DiskScheduler::new(disk_manager);
+        let buffer_pool_manager = BufferPoolManager::new(
+            disk_scheduler,
+            Replacer::<ReplacerNode>::new(1),
+            DEFAULT_PAGE_SIZE,
+            1,
+        )
+        .unwrap();
+        buffer_pool_manager.new_page().unwrap();
+        buffer_pool_manager.new_page().unwrap();
+
+        let mut wp = buffer_pool_manager.write_page(1).unwrap();
+        wp.write_all(b"page-1").unwrap();
+        drop(wp);
+
+        // Give up on the miss part way, the dirty page must survive
+        let mut load = Box::pin(buffer_pool_manager.read_page_async(2));
+        let mut cx = std::task::Context::from_waker(std::task::Waker::noop());
+        let _ = load.as_mut().poll(&mut cx);
+        drop(load);
+        assert_eq!(buffer_pool_manager.loading_pages(), 0);
+
+        let mut rp = buffer_pool_manager.read_page(1).unwrap();
+        let mut buf = [0_u8; DEFAULT_PAGE_SIZE];
+        rp.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf[..6], b"page-1");
+    }
+
+    #[test]
+    fn test_concurrent_misses_share_one_read() {
+        let temp_dir = tempdir::TempDir::new("test_buffer_pool_manager").unwrap();
+        let temp_file = temp_dir.path().join("test.db");
+        let disk_manager =
+            DiskManager::new(DEFAULT_PAGE_SIZE, Some(temp_file.clone()), false).unwrap();
+        let (tx, rx) = mpsc::channel();
+        disk_manager.increase_pages(20).unwrap();
+        for page_id in 1..=20 {
+            disk_manager.write_page(format!("page-{}", page_id).as_bytes(), page_id, tx.clone());
+            rx.recv().unwrap().unwrap();
+        }
+
+        let disk_scheduler = DiskScheduler::new(Arc::new(disk_manager));
+        let buffer_pool_manager = Arc::new(
+            BufferPoolManager::new(
+                disk_scheduler,
+                Replacer::<ReplacerNode>::new(32),
+                DEFAULT_PAGE_SIZE,
+                32,
+            )
+            .unwrap(),
+        );
+
+        // Every round hammers a different cold page
+        for page_id in 1..=20 {
+            let threads = 16;
+            let barrier = Arc::new(std::sync::Barrier::new(threads));
+            let readers = (0..threads)
+                .map(|t| {
+                    let buffer_pool_manager = buffer_pool_manager.clone();
+                    let barrier = barrier.clone();
+                    std::thread::spawn(move || {
+                        barrier.wait();
+                        let mut rp = if t % 2 == 0 {
+                            buffer_pool_manager.read_page(page_id).unwrap()
+                        } else {
+                            block_on(buffer_pool_manager.read_page_async(page_id)).unwrap()
+                        };
+                        let mut buf = [0_u8; DEFAULT_PAGE_SIZE];
+                        rp.read_exact(&mut buf).unwrap();
+
+                        let expected = format!("page-{}", page_id);
+                        assert_eq!(&buf[..expected.len()], expected.as_bytes());
+                    })
+                })
+                .collect::<Vec<_>>();
+            for reader in readers {
+                reader.join().unwrap();
+            }
+
+            // A second read of the page would have taken a second frame
+            assert_eq!(buffer_pool_manager.resident_pages(), page_id);
+            assert_eq!(
+                buffer_pool_manager.free_list.lock().unwrap().len(),
+                32 - page_id
+            );
+            assert_eq!(buffer_pool_manager.loading_pages(), 0);
+        }
+
+        drop(buffer_pool_manager);
+        temp_dir.close().unwrap();
+    }
+
+    #[test]
+    fn test_page_latches() {
+        let disk_manager = Arc::new(DiskManager::default());
+        let disk_scheduler = DiskScheduler::new(disk_manager);
+        let replacer = Replacer::<ReplacerNode>::new(3);
+        let buffer_pool_manager =
+            BufferPoolManager::new(disk_scheduler, replacer, DEFAULT_PAGE_SIZE, 3).unwrap();
+        let page_id = buffer_pool_manager.new_page().unwrap();
+
+        // Readers share the page, a writer has to wait for them
+        let rp = buffer_pool_manager.read_page(page_id).unwrap();
+        let rp2 = buffer_pool_manager.try_read_page(page_id).unwrap();
+        assert!(matches!(
+            buffer_pool_manager.try_write_page(page_id),
+            Err(Error::LockContention)
+        ));
+        let rp = rp.try_upgrade().err().unwrap();
+        drop(rp2);
+
+        let mut wp = rp.try_upgrade().ok().unwrap();
+        wp.write_all(b"foo").unwrap();
+        assert!(matches!(
+            buffer_pool_manager.try_read_page(page_id),
+            Err(Error::LockContention)
+        ));
+
+        let rp = wp.downgrade();
+        let mut rp2 = buffer_pool_manager.try_read_page(page_id).unwrap();
+        let mut buf = [0_u8; DEFAULT_PAGE_SIZE];
+        rp2.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf[..3], b"foo");
+
+        // Reading a page only shares its frame lock
+        let frame = rp.frame.read().unwrap();
+        assert_eq!(&rp2[..3], b"foo");
+        drop(frame);
+        drop((rp, rp2));
+
+        // A blocked upgrade goes through once the other reader leaves,
+        // and the pins are all let go in the end
+        let buffer_pool_manager = Arc::new(buffer_pool_manager);
+        let rp = buffer_pool_manager.read_page(page_id).unwrap();
+        let upgrader = {
+            let buffer_pool_manager = buffer_pool_manager.clone();
+            thread::spawn(move || {
+                let rp = buffer_pool_manager.read_page(page_id).unwrap();
+                let mut wp = rp.upgrade().ok().unwrap();
+                wp.seek(SeekFrom::Start(3)).unwrap();
+                wp.write_all(b"bar").unwrap();
+            })
+        };
+        let frame_id = buffer_pool_manager.frame_of(page_id).unwrap();
+        let latch = &buffer_pool_manager.latches[frame_id];
+        while latch.try_lock_shared() {
+            latch.unlock_shared();
+            thread::yield_now();
+        }
+        drop(rp);
+        upgrader.join().unwrap();
+
+        let mut rp = buffer_pool_manager.try_read_page(page_id).unwrap();
+        rp.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf[..6], b"foobar");
+        drop(rp);
+        let frame = buffer_pool_manager.frames[frame_id].read().unwrap();
+        assert_eq!(frame.pin_count.load(Relaxed), 0);
+    }
+
+    #[test]
+    fn test_free_map_pages_are_not_handed_out() {
+        let temp_dir = tempdir::TempDir::new("test_buffer_pool_manager").unwrap();
+        let temp_file = temp_dir.path().join("test.db");
+        let page_size = 64;
+
+        let disk_manager = DiskManager::new(page_size, Some(temp_file.clone()), false).unwrap();
+        let disk_scheduler = DiskScheduler::new(Arc::new(disk_manager));
+        let buffer_pool_manager = BufferPoolManager::new(
+            disk_scheduler,
+            Replacer::<ReplacerNode>::new(3),
+            page_size,
+            3,
+        )
+        .unwrap();
+
+        // Freeing page 190 outgrows the header and appends a free map
+        // page, which a later page id can reach past
+        for _ in 0..200 {
+            buffer_pool_manager.new_page().unwrap();
+        }
+        buffer_pool_manager.delete_page(190).unwrap();
+        let free_map_page = buffer_pool_manager.disk_scheduler.page_count().unwrap();
+        assert_eq!(free_map_page, 201);
+        assert_eq!(buffer_pool_manager.new_page().unwrap(), 190);
+        assert_eq!(buffer_pool_manager.new_page().unwrap(), 202);
+
+        assert!(matches!(
+            buffer_pool_manager.write_page(free_map_page),
+            Err(Error::PageNotAllocated { page_id: 201 })
+        ));
+        buffer_pool_manager.flush_all().unwrap();
+        drop(buffer_pool_manager);
+        DiskManager::open(page_size, temp_file).unwrap();
+    }
+
+    #[test]
+    fn test_delete_page() {
+        let disk_manager = Arc::new(DiskManager::default());
+        let disk_scheduler = DiskScheduler::new(disk_manager);
+        let replacer = Replacer::<ReplacerNode>::new(3);
+        let buffer_pool_manager =
+            BufferPoolManager::new(disk_scheduler, replacer, DEFAULT_PAGE_SIZE, 3).unwrap();
+
+        for i in 1..5 {
+            assert_eq!(buffer_pool_manager.new_page().unwrap(), i);
+        }
+
+        let mut wp = buffer_pool_manager.write_page(2).unwrap();
+        wp.write_all(b"foo").unwrap();
+
+        // Pinned pages can't be deleted
+        assert!(matches!(
+            buffer_pool_manager.delete_page(2),
+            Err(Error::PagePinned { page_id: 2 })
+        ));
+        drop(wp);
+        buffer_pool_manager.delete_page(2).unwrap();
+        assert_eq!(buffer_pool_manager.frame_of(2), None);
+
+        // A deleted page can't be read back until it is handed out again
+        assert!(matches!(
+            buffer_pool_manager.read_page(2),
+            Err(Error::PageNotAllocated { page_id: 2 })
+        ));
+
+        // Deleting twice or outside the file fails
+        assert!(matches!(
+            buffer_pool_manager.delete_page(2),
+            Err(Error::PageNotAllocated { page_id: 2 })
+        ));
+        assert!(matches!(
+            buffer_pool_manager.delete_page(10),
+            Err(Error::PageOutOfRange { page_id: 10 })
+        ));
+
+        // Deleting waits for the free list instead of reporting
+        // contention
+        let free_list = buffer_pool_manager.free_list.lock().unwrap();
+        thread::scope(|s| {
+            let deleter = s.spawn(|| buffer_pool_manager.delete_page(3));
+            thread::sleep(Duration::from_millis(10));
+            drop(free_list);
+            deleter.join().unwrap().unwrap();
+        });
+
+        // Freed ids are reused lowest first before the file grows
+        assert_eq!(buffer_pool_manager.new_page().unwrap(), 2);
+        assert_eq!(buffer_pool_manager.new_page().unwrap(), 3);
+        assert_eq!(buffer_pool_manager.new_page().unwrap(), 5);
+
+        let mut rp = buffer_pool_manager.read_page(2).unwrap();
+        let mut buf = [0_u8; DEFAULT_PAGE_SIZE];
+        rp.read_exact(&mut buf).unwrap();
+        assert_eq!(buf, [0_u8; DEFAULT_PAGE_SIZE]);
+    }
+
+    #[test]
+    fn test_page_table_partitions() {
+        // The ids one instance of a sharded pool owns still spread
+        // over every partition
+        for num_instances in [1, 4, 16] {
+            let used = (0..256)
+                .map(|i| partition_index(num_instances * i + 1, 16))
+                .collect::<HashSet<_>>();
+            assert_eq!(used.len(), 16, "{} instances", num_instances);
+        }
+
+        let disk_manager = Arc::new(DiskManager::default());
+        let partitioned = |num_partitions| {
+            BufferPoolManager::with_partitions(
+                DiskScheduler::new(Arc::clone(&disk_manager)),
+                Replacer::<ReplacerNode>::new(3),
+                DEFAULT_PAGE_SIZE,
+                3,
+                num_partitions,
+            )
+        };
+        assert!(matches!(
+            partitioned(0),
+            Err(Error::InvalidPartitionCount { num_partitions: 0 })
+        ));
+
+        let buffer_pool_manager = partitioned(4).unwrap();
+        assert_eq!(buffer_pool_manager.partitions.len(), 4);
+        for _ in 0..8 {
+            let page_id = buffer_pool_manager.new_page().unwrap();
+            buffer_pool_manager.read_page(page_id).unwrap();
+        }
+        assert_eq!(buffer_pool_manager.resident_pages(), 3);
+    }
+
     #[test]
     fn test_page_contention() {}
 }
@@ -1,5 +1,9 @@
-pub trait Evictable<T> {
-    fn new(id: usize) -> T;
+use crate::replacement_policy::ReplacementPolicy;
+use std::collections::HashMap;
+use std::sync::{Mutex, MutexGuard, PoisonError};
+
+pub trait Evictable {
+    fn new(id: usize) -> Self;
 
     fn pinned(&self) -> bool;
 
@@ -11,29 +15,48 @@ pub enum EvictionError {
     NoFreeBuffer,
 }
 
+/// Usage counts saturate here, the same cap Postgres uses for
+/// its buffer usage_count.
+pub(crate) const MAX_USAGE_COUNT: u8 = 5;
+
 /// [Replacer] implements the page replacement policy.
 /// The eviction policy modeled here is similar to
 /// Postgres' CLOCK-sweep algorithm.
 pub struct Replacer<T>
 where
-    T: Evictable<T>,
+    T: Evictable,
 {
     size: usize,
+    state: Mutex<ClockState<T>>,
+}
+
+struct ClockState<T> {
     node_store: Vec<Option<T>>,
     ref_bits: Vec<u8>,
+    /// evictable is cleared for nodes the owner has pinned
+    evictable: Vec<bool>,
+    /// index maps a node id to its slot in node_store
+    index: HashMap<usize, usize>,
+    /// free_slots holds the empty slots, lowest slot last
+    free_slots: Vec<usize>,
     ref_pos: usize,
 }
 
 impl<T> Replacer<T>
 where
-    T: Evictable<T> + Clone,
+    T: Evictable + Clone,
 {
     pub fn new(size: usize) -> Self {
         Self {
             size,
-            node_store: vec![None; size],
-            ref_bits: vec![0; size],
-            ref_pos: 0,
+            state: Mutex::new(ClockState {
+                node_store: vec![None; size],
+                ref_bits: vec![0; size],
+                evictable: vec![true; size],
+                index: HashMap::with_capacity(size),
+                free_slots: (0..size).rev().collect(),
+                ref_pos: 0,
+            }),
         }
     }
 
@@ -41,52 +64,164 @@ where
         self.size
     }
 
-    pub fn insert_and_evict(&mut self, node_id: usize) -> Result<Option<T>, EvictionError> {
-        let new_node = Some(T::new(node_id));
-        for (i, page) in self.node_store.iter().enumerate() {
-            match page {
-                Some(v) => {
-                    if v.id() == node_id {
-                        self.ref_pos = i;
-                        self.ref_bits[i] += 1;
-                        return Ok(None);
-                    }
-                }
-                None => {
-                    self.node_store[i] = new_node;
-                    return Ok(None);
-                }
-            };
+    /// insert_and_evict records an access to a node. A node that is
+    /// already tracked has its usage count bumped, a new node takes
+    /// an empty slot or, once the store is full, the slot of a victim
+    /// which is returned. Lookups and inserts are O(1).
+    pub fn insert_and_evict(&self, node_id: usize) -> Result<Option<T>, EvictionError> {
+        let mut state = self.state();
+        if let Some(&i) = state.index.get(&node_id) {
+            state.ref_bits[i] = state.ref_bits[i].saturating_add(1).min(MAX_USAGE_COUNT);
+            return Ok(None);
         }
 
-        loop {
-            let curr_index = self.ref_pos % self.size;
-            if self.ref_pos >= self.size {
-                return Err(EvictionError::NoFreeBuffer);
+        let (slot, evicted) = match state.free_slots.pop() {
+            Some(slot) => (slot, None),
+            None => {
+                let victim = state.sweep(self.size)?;
+                (victim, state.take(victim))
             }
+        };
+
+        state.node_store[slot] = Some(T::new(node_id));
+        state.evictable[slot] = true;
+        state.index.insert(node_id, slot);
+        Ok(evicted)
+    }
+
+    /// remove drops a node from the store without evicting it, for
+    /// frames that are released back to the free list.
+    pub fn remove(&self, node_id: usize) -> Option<T> {
+        let mut state = self.state();
+        let slot = *state.index.get(&node_id)?;
+        let node = state.take(slot);
+        state.free_slots.push(slot);
+        node
+    }
+
+    /// evict sweeps the clock hand over the node store, decrementing
+    /// reference counts until it finds an unpinned node with a zero
+    /// count. The node is removed and its slot left empty. The sweep
+    /// is bounded so a store of pinned nodes errors out instead of
+    /// spinning forever.
+    pub fn evict(&self) -> Result<T, EvictionError> {
+        let mut state = self.state();
+        let victim = state.sweep(self.size)?;
+        let node = state.take(victim).ok_or(EvictionError::NoFreeBuffer)?;
+        state.free_slots.push(victim);
+        Ok(node)
+    }
+
+    /// set_evictable pins or unpins a node. Pinned nodes are skipped
+    /// by the sweep.
+    pub fn set_evictable(&self, node_id: usize, evictable: bool) {
+        let mut state = self.state();
+        if let Some(&slot) = state.index.get(&node_id) {
+            state.evictable[slot] = evictable;
+        }
+    }
+
+    /// ahead_of_hand returns the ids of the unpinned nodes in the
+    /// lookahead slots from the hand, in sweep order.
+    pub fn ahead_of_hand(&self, lookahead: usize) -> Vec<usize> {
+        let state = self.state();
+        (0..lookahead.min(self.size))
+            .map(|step| (state.ref_pos + step) % self.size)
+            .filter(|&slot| state.evictable[slot])
+            .filter_map(|slot| state.node_store[slot].as_ref())
+            .filter(|node| !node.pinned())
+            .map(Evictable::id)
+            .collect()
+    }
+
+    /// state hands out the clock state. A panic can't leave the state
+    /// half updated, so a poisoned lock is still safe to use.
+    fn state(&self) -> MutexGuard<'_, ClockState<T>> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl<T> ClockState<T>
+where
+    T: Evictable,
+{
+    /// take empties a slot, leaving the caller to reuse or free it.
+    fn take(&mut self, slot: usize) -> Option<T> {
+        let node = self.node_store[slot].take()?;
+        self.index.remove(&node.id());
+        self.ref_bits[slot] = 0;
+        Some(node)
+    }
+
+    /// sweep returns the slot index of the next victim.
+    fn sweep(&mut self, size: usize) -> Result<usize, EvictionError> {
+        if size == 0 {
+            return Err(EvictionError::NoFreeBuffer);
+        }
+
+        for _ in 0..size * (MAX_USAGE_COUNT as usize + 1) {
+            let curr_index = self.ref_pos % size;
 
             // Advances the reference pointer to the next possible index
-            self.ref_pos = curr_index + 1;
+            self.ref_pos = (curr_index + 1) % size;
+
+            match &self.node_store[curr_index] {
+                Some(node) if !node.pinned() && self.evictable[curr_index] => {}
+                _ => continue,
+            }
 
             if self.ref_bits[curr_index] > 0 {
                 self.ref_bits[curr_index] -= 1;
             } else {
-                let owned_node = &mut self.node_store[curr_index];
-                let owned_node = owned_node.take().unwrap();
-
-                if !owned_node.pinned() {
-                    let evicted = std::mem::replace(&mut self.node_store[curr_index], new_node);
-                    return Ok(evicted);
-                }
+                return Ok(curr_index);
             }
         }
+
+        Err(EvictionError::NoFreeBuffer)
     }
 }
 
+impl<T> ReplacementPolicy for Replacer<T>
+where
+    T: Evictable + Clone + Send,
+{
+    /// record_access takes a free slot for a new frame. The pool
+    /// checks the replacer is as large as it is, so a slot is always
+    /// free and nothing is evicted here.
+    fn record_access(&self, frame_id: usize, _page_id: usize) {
+        let evicted = self.insert_and_evict(frame_id);
+        debug_assert!(
+            matches!(evicted, Ok(None)),
+            "replacer smaller than the pool"
+        );
+    }
+
+    fn set_evictable(&self, frame_id: usize, evictable: bool) {
+        Replacer::set_evictable(self, frame_id, evictable)
+    }
+
+    fn evict(&self) -> Result<usize, EvictionError> {
+        Replacer::evict(self).map(|node| node.id())
+    }
+
+    fn remove(&self, frame_id: usize) {
+        Replacer::remove(self, frame_id);
+    }
+
+    fn ahead_of_hand(&self, lookahead: usize) -> Option<Vec<usize>> {
+        Some(Replacer::ahead_of_hand(self, lookahead))
+    }
+
+    fn capacity(&self) -> Option<usize> {
+        Some(self.size)
+    }
+}
+
+#[cfg(test)]
 mod tests {
     use super::*;
 
-    impl Evictable<u8> for u8 {
+    impl Evictable for u8 {
         fn new(id: usize) -> u8 {
             id as u8
         }
@@ -102,7 +237,7 @@ mod tests {
 
     #[test]
     fn test_replacer() {
-        let mut p = Replacer::<u8>::new(5);
+        let p = Replacer::<u8>::new(5);
 
         assert_eq!(p.size(), 5);
         // Inserting 5 elements in to Replacer
@@ -112,8 +247,70 @@ mod tests {
         assert_eq!(p.insert_and_evict(6).unwrap(), None);
         assert_eq!(p.insert_and_evict(7).unwrap(), None);
         assert_eq!(
-            p.node_store,
+            p.state().node_store,
             vec![Some(3), Some(4), Some(5), Some(6), Some(7)]
         );
     }
+
+    #[test]
+    fn test_replacer_evict() {
+        let p = Replacer::<u8>::new(3);
+        for i in 0..3 {
+            p.insert_and_evict(i).unwrap();
+        }
+
+        // Accessing 0 and 2 again leaves 1 as the only cold node
+        p.insert_and_evict(0).unwrap();
+        p.insert_and_evict(2).unwrap();
+        assert_eq!(p.insert_and_evict(9).unwrap(), Some(1));
+        assert_eq!(p.state().node_store, vec![Some(0), Some(9), Some(2)]);
+
+        // The hand continues past 9, so 2 loses its reference and 0 goes
+        assert_eq!(p.ahead_of_hand(3), vec![2, 0, 9]);
+        assert_eq!(p.evict().unwrap(), 0);
+        assert_eq!(p.state().node_store, vec![None, Some(9), Some(2)]);
+
+        assert_eq!(p.remove(2), Some(2));
+        assert_eq!(p.remove(2), None);
+        assert_eq!(p.state().node_store, vec![None, Some(9), None]);
+    }
+
+    #[test]
+    fn test_replacer_set_evictable() {
+        let p = Replacer::<u8>::new(3);
+        for i in 0..3 {
+            p.insert_and_evict(i).unwrap();
+        }
+
+        p.set_evictable(0, false);
+        p.set_evictable(1, false);
+        assert_eq!(ReplacementPolicy::evict(&p).unwrap(), 2);
+
+        // Every node left is pinned
+        assert!(matches!(
+            ReplacementPolicy::evict(&p),
+            Err(EvictionError::NoFreeBuffer)
+        ));
+        p.set_evictable(1, true);
+        assert_eq!(ReplacementPolicy::evict(&p).unwrap(), 1);
+    }
+
+    #[test]
+    fn test_replacer_reuses_slots() {
+        let p = Replacer::<u8>::new(3);
+        for i in 0..3 {
+            p.insert_and_evict(i).unwrap();
+        }
+
+        // An access past an empty slot finds the node, not the hole
+        p.remove(0);
+        assert_eq!(p.insert_and_evict(2).unwrap(), None);
+        assert_eq!(p.state().node_store, vec![None, Some(1), Some(2)]);
+        assert_eq!(p.state().ref_bits, vec![0, 0, 1]);
+
+        assert_eq!(p.insert_and_evict(5).unwrap(), None);
+        assert_eq!(p.insert_and_evict(6).unwrap(), Some(5));
+        assert_eq!(p.state().node_store, vec![Some(6), Some(1), Some(2)]);
+        assert_eq!(p.state().index.len(), 3);
+    }
 }
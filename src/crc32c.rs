@@ -0,0 +1,78 @@
+/// Reflected form of the Castagnoli polynomial used by CRC32C.
+const POLYNOMIAL: u32 = 0x82f6_3b78;
+
+const TABLE: [u32; 256] = build_table();
+
+const fn build_table() -> [u32; 256] {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ POLYNOMIAL
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+/// [Crc32c] computes a CRC32C checksum incrementally, so a page and
+/// the metadata mixed in to its checksum don't need to be contiguous.
+pub struct Crc32c {
+    crc: u32,
+}
+
+impl Default for Crc32c {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Crc32c {
+    pub fn new() -> Self {
+        Self { crc: !0 }
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        for byte in data {
+            let index = (self.crc ^ *byte as u32) & 0xff;
+            self.crc = (self.crc >> 8) ^ TABLE[index as usize];
+        }
+    }
+
+    pub fn finish(&self) -> u32 {
+        !self.crc
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn crc32c(data: &[u8]) -> u32 {
+        let mut crc = Crc32c::new();
+        crc.update(data);
+        crc.finish()
+    }
+
+    #[test]
+    fn test_crc32c() {
+        // Check values from RFC 3720, appendix B.4
+        assert_eq!(crc32c(b""), 0);
+        assert_eq!(crc32c(b"123456789"), 0xe306_9283);
+        assert_eq!(crc32c(&[0; 32]), 0x8a91_36aa);
+        assert_eq!(crc32c(&[0xff; 32]), 0x62a8_ab43);
+
+        let mut crc = Crc32c::new();
+        crc.update(b"1234");
+        crc.update(b"56789");
+        assert_eq!(crc.finish(), 0xe306_9283);
+    }
+}
@@ -1,32 +1,165 @@
+use crate::crc32c::Crc32c;
+use crate::error::{Error, Result};
+use crate::io_future::Completion;
 use crate::DEFAULT_PAGE_SIZE;
+use serde::{Deserialize, Serialize};
 use std::fs::{File, OpenOptions};
-use std::io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::os::unix::fs::FileExt;
 use std::path::PathBuf;
-use std::sync::mpsc::Sender;
-use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+
+/// Magic number stamped at the start of every data file, "COMPOSTR".
+const MAGIC: u64 = 0x434f_4d50_4f53_5452;
+const FORMAT_VERSION: u32 = 4;
+
+/// Every page in a data file is followed by a CRC32C trailer.
+pub const PAGE_TRAILER_SIZE: usize = 4;
+
+/// Free map overflow pages start with the page id of the next one.
+const FREE_MAP_LINK_SIZE: usize = 8;
+
+/// [Superblock] is the header kept in the reserved first page of a
+/// data file. Page ids start at 1 so page 0 is never handed out.
+/// The rest of the header page holds the start of the free page
+/// bitmap, which continues in a chain of overflow pages once the
+/// file outgrows it.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+struct Superblock {
+    magic: u64,
+    version: u32,
+    page_size: u64,
+    page_count: u64,
+    free_map_page: u64,
+    /// checkpoint numbers the last completed checkpoint, 0 if there
+    /// hasn't been one
+    checkpoint: u64,
+}
+
+impl Superblock {
+    fn new(page_size: usize, page_count: usize, free_map_page: usize, checkpoint: u64) -> Self {
+        Self {
+            magic: MAGIC,
+            version: FORMAT_VERSION,
+            page_size: page_size as u64,
+            page_count: page_count as u64,
+            free_map_page: free_map_page as u64,
+            checkpoint,
+        }
+    }
+}
+
+/// [IoPriority] is the class a request is scheduled in. Classes are
+/// listed from most to least urgent.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum IoPriority {
+    /// A reader is blocked waiting on the page.
+    ForegroundRead,
+    /// A writer is blocked waiting on the page, such as a dirty victim
+    /// being written back before its frame is reused.
+    ForegroundWrite,
+    /// Dirty pages written out ahead of time.
+    BackgroundFlush,
+    /// Pages read in before anyone has asked for them.
+    Prefetch,
+}
+
+impl IoPriority {
+    /// deadline is how long a request may wait behind more urgent
+    /// work. Past its deadline a request is served ahead of every
+    /// class, so low priority work always makes progress.
+    pub fn deadline(&self) -> Duration {
+        match self {
+            IoPriority::ForegroundRead => Duration::from_millis(5),
+            IoPriority::ForegroundWrite => Duration::from_millis(10),
+            IoPriority::BackgroundFlush => Duration::from_millis(100),
+            IoPriority::Prefetch => Duration::from_millis(200),
+        }
+    }
+}
 
 pub enum DiskManagerRequest {
     DiskRwRequest {
         is_write: bool,
         data: Arc<Mutex<Vec<u8>>>,
         page_id: usize,
-        callback: Sender<bool>,
+        priority: IoPriority,
+        callback: Completion,
     },
 }
 
+impl DiskManagerRequest {
+    pub fn page_id(&self) -> usize {
+        match self {
+            DiskManagerRequest::DiskRwRequest { page_id, .. } => *page_id,
+        }
+    }
+
+    pub fn priority(&self) -> IoPriority {
+        match self {
+            DiskManagerRequest::DiskRwRequest { priority, .. } => *priority,
+        }
+    }
+
+    pub fn is_write(&self) -> bool {
+        match self {
+            DiskManagerRequest::DiskRwRequest { is_write, .. } => *is_write,
+        }
+    }
+
+    pub(crate) fn data(&self) -> &Arc<Mutex<Vec<u8>>> {
+        match self {
+            DiskManagerRequest::DiskRwRequest { data, .. } => data,
+        }
+    }
+}
+
+/// [PageMap] tracks which pages exist. It is the only mutable state
+/// shared by IO workers, page IO itself never touches it.
+struct PageMap {
+    page_count: usize,
+    /// free_map has one bit per page id, set when the page is free.
+    free_map: Vec<u8>,
+    free_map_pages: Vec<usize>,
+}
+
+impl PageMap {
+    fn is_free(&self, p_id: usize) -> bool {
+        self.free_map
+            .get(p_id / 8)
+            .is_some_and(|byte| byte & (1 << (p_id % 8)) != 0)
+    }
+
+    /// is_allocated is true for pages in use by callers, free map
+    /// overflow pages share the id space but are never handed out.
+    fn is_allocated(&self, p_id: usize) -> bool {
+        p_id != 0
+            && p_id <= self.page_count
+            && !self.is_free(p_id)
+            && !self.free_map_pages.contains(&p_id)
+    }
+}
+
+/// [DiskManager] reads and writes pages in the data file, or in a
+/// memory buffer when running in memory. Page IO only needs a shared
+/// reference, data files are accessed with positional reads and
+/// writes so requests for unrelated pages can run in parallel.
 pub struct DiskManager {
     page_dir: Option<PathBuf>,
     file_handle: Option<File>,
-    in_memory_pages: Option<Vec<u8>>,
+    in_memory_pages: Option<RwLock<Vec<u8>>>,
     in_memory: bool,
     page_size: usize,
+    page_map: Mutex<PageMap>,
+    checkpoint: AtomicU64,
 }
 
 impl Default for DiskManager {
     fn default() -> Self {
         // Default page size is 4kb.
         let page_size = DEFAULT_PAGE_SIZE;
-        Self::new(page_size, None, true)
+        Self::new(page_size, None, true).expect("in memory disk manager can't fail")
     }
 }
 
@@ -35,143 +168,566 @@ impl DiskManager {
         page_size: usize,
         page_dir: Option<std::path::PathBuf>,
         in_memory: bool,
-    ) -> DiskManager {
+    ) -> Result<DiskManager> {
+        Self::check_page_size(page_size)?;
         let mut in_memory_pages = None;
         let mut file_handle = None;
+        let mut free_map = vec![];
         if in_memory {
-            in_memory_pages = Some(Vec::new());
+            in_memory_pages = Some(RwLock::new(Vec::new()));
         } else {
-            let pd = page_dir.clone();
-            in_memory_pages = None;
-            let file = match Self::open_data_file(&pd.unwrap()) {
-                Ok(f) => f,
-                Err(err) => panic!("{}", err),
-            };
-
-            file_handle = Some(file);
+            let pd = page_dir.as_ref().ok_or_else(|| {
+                std::io::Error::new(std::io::ErrorKind::InvalidInput, "no data file path")
+            })?;
+            file_handle = Some(Self::open_data_file(pd)?);
+            free_map = vec![0; page_size - Self::header_len()];
         }
 
-        DiskManager {
+        let dm = DiskManager {
             page_dir,
             in_memory,
             in_memory_pages,
             page_size,
             file_handle,
+            page_map: Mutex::new(PageMap {
+                page_count: 0,
+                free_map,
+                free_map_pages: vec![],
+            }),
+            checkpoint: AtomicU64::new(0),
+        };
+
+        if !in_memory {
+            let page_map = dm.page_map.lock()?;
+            dm.write_header(&page_map)?;
         }
+
+        Ok(dm)
     }
 
-    pub fn write_page(&mut self, data: &mut Vec<u8>, page_id: usize, callback: Sender<bool>) {
-        let mut is_okay = true;
-        if self.in_memory {
-            match self.write_in_memory(page_id, data) {
-                Ok(_) => {
-                    is_okay = true;
-                }
-                Err(_) => {
-                    is_okay = false;
-                }
+    /// open reopens an existing data file without truncating it. The
+    /// superblock is validated against the expected page size and the
+    /// page count is restored from it.
+    pub fn open(page_size: usize, page_dir: PathBuf) -> Result<DiskManager> {
+        Self::check_page_size(page_size)?;
+        let file = OpenOptions::new().read(true).write(true).open(&page_dir)?;
+
+        let header_len = Self::header_len();
+        let mut buf = vec![0; header_len];
+        file.read_exact_at(&mut buf, 0)?;
+
+        let header: Superblock = bincode::deserialize(&buf)
+            .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidData, err))?;
+        if header.magic != MAGIC {
+            return Err(Error::InvalidMagic(header.magic));
+        }
+        if header.version != FORMAT_VERSION {
+            return Err(Error::UnsupportedVersion(header.version));
+        }
+        if header.page_size as usize != page_size {
+            return Err(Error::PageSizeMismatch {
+                expected: page_size,
+                found: header.page_size as usize,
+            });
+        }
+
+        let slot_size = page_size + PAGE_TRAILER_SIZE;
+        let page_count = header.page_count as usize;
+        let file_pages = (file.metadata()?.len() as usize / slot_size).saturating_sub(1);
+        if file_pages < page_count {
+            return Err(Error::PageCountMismatch {
+                header: page_count,
+                file: file_pages,
+            });
+        }
+
+        let dm = DiskManager {
+            page_dir: Some(page_dir),
+            file_handle: Some(file),
+            in_memory_pages: None,
+            in_memory: false,
+            page_size,
+            page_map: Mutex::new(PageMap {
+                page_count,
+                free_map: vec![],
+                free_map_pages: vec![],
+            }),
+            checkpoint: AtomicU64::new(header.checkpoint),
+        };
+
+        let header_page = dm.read_slot(0)?;
+        let mut page_map = dm.page_map.lock()?;
+        page_map.free_map = header_page[header_len..].to_vec();
+
+        // Follow the chain of free map overflow pages. A bad link is
+        // reported against the page it was read from
+        let mut next = header.free_map_page as usize;
+        while next != 0 {
+            if next > page_count || page_map.free_map_pages.contains(&next) {
+                let page_id = page_map.free_map_pages.last().copied().unwrap_or(0);
+                return Err(Error::Corruption { page_id });
             }
+
+            let map_page = dm.read_slot(next)?;
+            page_map.free_map_pages.push(next);
+            page_map
+                .free_map
+                .extend_from_slice(&map_page[FREE_MAP_LINK_SIZE..]);
+            next = read_link(&map_page);
+        }
+
+        drop(page_map);
+        Ok(dm)
+    }
+
+    pub fn write_page(&self, data: &[u8], page_id: usize, callback: impl Into<Completion>) {
+        let result = if self.in_memory {
+            self.write_in_memory(page_id, data)
         } else {
-            match self.write_disk(page_id, data) {
-                Ok(_) => {
-                    is_okay = true;
-                }
-                Err(_) => {
-                    is_okay = false;
-                }
+            self.write_disk(page_id, data)
+        };
+
+        callback.into().complete(result);
+    }
+
+    pub fn read_page(&self, data: &mut [u8], page_id: usize, callback: impl Into<Completion>) {
+        let result = if self.in_memory {
+            self.read_in_memory(page_id, data)
+        } else {
+            self.read_disk(page_id, data)
+        };
+
+        callback.into().complete(result);
+    }
+
+    /// write_pages writes a run of consecutive pages starting at
+    /// first_page_id. On disk the run is written with a single
+    /// positional write. A failure may leave part of the run written.
+    pub fn write_pages(&self, first_page_id: usize, pages: &[&[u8]]) -> Result<()> {
+        if self.in_memory {
+            // No syscalls to save, so pages are copied one at a time
+            return pages
+                .iter()
+                .enumerate()
+                .try_for_each(|(i, page)| self.write_in_memory(first_page_id + i, page));
+        }
+
+        let slot_size = self.slot_size();
+        let mut slots = vec![0; slot_size * pages.len()];
+        for (i, (page, slot)) in pages.iter().zip(slots.chunks_mut(slot_size)).enumerate() {
+            self.page_offset(first_page_id + i, page.len())?;
+            self.fill_slot(first_page_id + i, page, slot);
+        }
+
+        let file = self.file_handle.as_ref().unwrap();
+        file.write_all_at(&slots, (first_page_id * slot_size) as u64)?;
+        Ok(())
+    }
+
+    /// read_pages reads a run of consecutive pages starting at
+    /// first_page_id with a single positional read. Every page in the
+    /// run is verified and the first corrupted page fails the run.
+    pub fn read_pages(&self, first_page_id: usize, pages: &mut [&mut [u8]]) -> Result<()> {
+        if self.in_memory {
+            return pages
+                .iter_mut()
+                .enumerate()
+                .try_for_each(|(i, page)| self.read_in_memory(first_page_id + i, page));
+        }
+
+        for (i, page) in pages.iter().enumerate() {
+            self.page_offset(first_page_id + i, page.len())?;
+        }
+
+        let slot_size = self.slot_size();
+        let mut slots = vec![0; slot_size * pages.len()];
+        let file = self.file_handle.as_ref().unwrap();
+        file.read_exact_at(&mut slots, (first_page_id * slot_size) as u64)?;
+
+        for (i, (page, slot)) in pages.iter_mut().zip(slots.chunks(slot_size)).enumerate() {
+            if !self.verify_slot(first_page_id + i, slot) {
+                return Err(Error::Corruption {
+                    page_id: first_page_id + i,
+                });
             }
+
+            let len = page.len();
+            page.copy_from_slice(&slot[..len]);
+        }
+
+        Ok(())
+    }
+
+    /// sync flushes the data file, including its metadata, to stable
+    /// storage. In memory there is nothing to flush.
+    pub fn sync(&self) -> Result<()> {
+        if let Some(file) = self.file_handle.as_ref() {
+            file.sync_all()?;
+        }
+
+        Ok(())
+    }
+
+    /// record_checkpoint numbers a completed checkpoint and records it
+    /// in the header. The data file is synced before the header is
+    /// written, so the marker never lands ahead of the pages it
+    /// covers, and again after.
+    pub fn record_checkpoint(&self) -> Result<u64> {
+        let page_map = self.page_map.lock()?;
+        self.sync()?;
+
+        let checkpoint = self.checkpoint.fetch_add(1, Ordering::Relaxed) + 1;
+        if !self.in_memory {
+            self.write_header(&page_map)?;
+            self.sync()?;
         }
 
-        callback.send(is_okay).expect("failed to send to channel");
+        Ok(checkpoint)
     }
 
-    pub fn read_page(&mut self, data: &mut Vec<u8>, page_id: usize, callback: Sender<bool>) {
-        let mut is_okay = true;
+    /// last_checkpoint is the number of the last checkpoint recorded,
+    /// 0 if there hasn't been one.
+    pub fn last_checkpoint(&self) -> u64 {
+        self.checkpoint.load(Ordering::Relaxed)
+    }
+
+    /// verify scans every page in the data file and returns the ids
+    /// of pages whose checksum doesn't match their contents.
+    pub fn verify(&self) -> Result<Vec<usize>> {
         if self.in_memory {
-            match self.read_in_memory(page_id, data) {
-                Ok(_) => {
-                    is_okay = true;
-                }
-                Err(_) => {
-                    is_okay = false;
-                }
+            return Ok(vec![]);
+        }
+
+        let mut corrupted = vec![];
+        let mut slot = vec![0; self.slot_size()];
+        let file = self.file_handle.as_ref().unwrap();
+        for page_id in 1..=self.page_count() {
+            file.read_exact_at(&mut slot, (page_id * self.slot_size()) as u64)?;
+            if !self.verify_slot(page_id, &slot) {
+                corrupted.push(page_id);
             }
-        } else {
-            match self.read_disk(page_id, data) {
-                Ok(_) => {
-                    is_okay = true;
-                }
-                Err(_) => {
-                    is_okay = false;
-                }
+        }
+
+        Ok(corrupted)
+    }
+
+    pub fn increase_pages(&self, p_id: usize) -> Result<()> {
+        let mut page_map = self.page_map.lock()?;
+        self.grow(&mut page_map, p_id)
+    }
+
+    pub fn page_count(&self) -> usize {
+        self.page_map
+            .lock()
+            .map(|page_map| page_map.page_count)
+            .unwrap_or_else(|err| err.into_inner().page_count)
+    }
+
+    /// allocate_page hands out the lowest free page id, only growing
+    /// the page file when no freed page is available for reuse.
+    pub fn allocate_page(&self) -> Result<usize> {
+        let mut page_map = self.page_map.lock()?;
+        let free_page = page_map
+            .free_map
+            .iter()
+            .enumerate()
+            .find(|(_, byte)| **byte != 0)
+            .map(|(i, byte)| i * 8 + byte.trailing_zeros() as usize);
+
+        match free_page {
+            Some(p_id) if p_id <= page_map.page_count => {
+                self.set_free(&mut page_map, p_id, false)?;
+                Ok(p_id)
             }
+            _ => {
+                let p_id = page_map.page_count + 1;
+                self.grow(&mut page_map, p_id)?;
+                Ok(p_id)
+            }
+        }
+    }
+
+    /// decrease_pages releases a page back to the free map. The page
+    /// is blanked so it reads back as a new page once it is reused.
+    pub fn decrease_pages(&self, p_id: usize) -> Result<()> {
+        let mut page_map = self.page_map.lock()?;
+        if p_id == 0 || p_id > page_map.page_count {
+            return Err(Error::PageOutOfRange { page_id: p_id });
+        }
+        if !page_map.is_allocated(p_id) {
+            return Err(Error::PageNotAllocated { page_id: p_id });
+        }
+
+        let blank = vec![0; self.page_size];
+        if self.in_memory {
+            self.write_in_memory(p_id, &blank)?;
+        } else {
+            self.write_disk(p_id, &blank)?;
         }
 
-        callback.send(is_okay).expect("failed to send to channel");
+        self.set_free(&mut page_map, p_id, true)
     }
 
-    pub fn increase_pages(&mut self, p_id: usize) {
+    pub fn is_free(&self, p_id: usize) -> bool {
+        self.page_map
+            .lock()
+            .is_ok_and(|page_map| page_map.is_free(p_id))
+    }
+
+    pub fn is_allocated(&self, p_id: usize) -> bool {
+        self.page_map
+            .lock()
+            .is_ok_and(|page_map| page_map.is_allocated(p_id))
+    }
+
+    fn grow(&self, page_map: &mut PageMap, p_id: usize) -> Result<()> {
+        if p_id <= page_map.page_count {
+            return Ok(());
+        }
+
+        if let Some(pages) = self.in_memory_pages.as_ref() {
+            pages.write()?.resize(p_id * self.page_size, 0);
+        } else if let Some(file) = self.file_handle.as_ref() {
+            file.set_len(((p_id + 1) * self.slot_size()) as u64)?;
+        }
+
+        page_map.page_count = p_id;
+        if !self.in_memory {
+            self.write_header(page_map)?;
+        }
+
+        Ok(())
+    }
+
+    fn set_free(&self, page_map: &mut PageMap, p_id: usize, free: bool) -> Result<()> {
         if self.in_memory {
-            let pages = self.in_memory_pages.as_mut().unwrap();
-            pages.resize(p_id * self.page_size, 0);
+            if page_map.free_map.len() <= p_id / 8 {
+                page_map.free_map.resize(p_id / 8 + 1, 0);
+            }
+        } else {
+            while page_map.free_map.len() <= p_id / 8 {
+                self.add_free_map_page(page_map)?;
+            }
+        }
+
+        if free {
+            page_map.free_map[p_id / 8] |= 1 << (p_id % 8);
+        } else {
+            page_map.free_map[p_id / 8] &= !(1 << (p_id % 8));
+        }
+
+        if self.in_memory {
+            return Ok(());
+        }
+
+        let header_capacity = self.page_size - Self::header_len();
+        if p_id / 8 < header_capacity {
+            self.write_header(page_map)?;
+        } else {
+            let index = (p_id / 8 - header_capacity) / (self.page_size - FREE_MAP_LINK_SIZE);
+            self.write_free_map_page(page_map, index)?;
+        }
+
+        Ok(())
+    }
+
+    /// add_free_map_page extends the free map by appending an overflow
+    /// page to the file and linking it on to the end of the chain.
+    fn add_free_map_page(&self, page_map: &mut PageMap) -> Result<()> {
+        let p_id = page_map.page_count + 1;
+        self.grow(page_map, p_id)?;
+
+        page_map.free_map_pages.push(p_id);
+        let free_map_len = page_map.free_map.len() + self.page_size - FREE_MAP_LINK_SIZE;
+        page_map.free_map.resize(free_map_len, 0);
+
+        let index = page_map.free_map_pages.len() - 1;
+        self.write_free_map_page(page_map, index)?;
+        if index == 0 {
+            self.write_header(page_map)?;
         } else {
-            let file = self.file_handle.as_mut().unwrap();
-            file.set_len((p_id * self.page_size) as u64).unwrap();
+            self.write_free_map_page(page_map, index - 1)?;
         }
+
+        Ok(())
     }
 
-    pub fn decrease_pages(&mut self, _p_id: usize) {
-        unimplemented!()
+    fn write_free_map_page(&self, page_map: &PageMap, index: usize) -> Result<()> {
+        let chunk_size = self.page_size - FREE_MAP_LINK_SIZE;
+        let start = self.page_size - Self::header_len() + index * chunk_size;
+        let next = page_map.free_map_pages.get(index + 1).copied().unwrap_or(0);
+
+        let mut page = Vec::with_capacity(self.page_size);
+        page.extend_from_slice(&(next as u64).to_le_bytes());
+        page.extend_from_slice(&page_map.free_map[start..start + chunk_size]);
+        self.write_slot(page_map.free_map_pages[index], &page)
     }
 
-    fn write_in_memory(&mut self, p_id: usize, p_data: &mut Vec<u8>) -> Result<(), std::io::Error> {
-        let pages = match &mut self.in_memory_pages {
+    fn write_in_memory(&self, p_id: usize, p_data: &[u8]) -> Result<()> {
+        let offset = self.page_offset(p_id, p_data.len())?;
+        let mut pages = match &self.in_memory_pages {
             None => {
-                return Err(std::io::Error::new(
+                return Err(Error::Io(std::io::Error::new(
                     std::io::ErrorKind::InvalidInput,
                     "no pages available",
-                ));
+                )));
             }
-            Some(pages) => pages,
+            Some(pages) => pages.write()?,
         };
 
-        let offset = (p_id - 1) * self.page_size;
-        pages[offset..self.page_size].copy_from_slice(&p_data);
+        if offset + p_data.len() > pages.len() {
+            return Err(Error::PageOutOfRange { page_id: p_id });
+        }
+
+        pages[offset..offset + p_data.len()].copy_from_slice(p_data);
         Ok(())
     }
 
-    fn write_disk(&self, p_id: usize, p_data: &mut Vec<u8>) -> Result<(), std::io::Error> {
-        let mut buf_writer = BufWriter::new(self.file_handle.as_ref().unwrap());
-        buf_writer.seek(SeekFrom::Start(((p_id - 1) * self.page_size) as u64))?;
-        buf_writer.write_all(p_data)?;
+    /// write_disk always writes a whole page slot, zero padding short
+    /// buffers, so the trailer checksum covers the entire page.
+    fn write_disk(&self, p_id: usize, p_data: &[u8]) -> Result<()> {
+        self.page_offset(p_id, p_data.len())?;
+        self.write_slot(p_id, p_data)
+    }
+
+    fn write_slot(&self, p_id: usize, p_data: &[u8]) -> Result<()> {
+        let offset = p_id * self.slot_size();
+        let mut slot = vec![0; self.slot_size()];
+        self.fill_slot(p_id, p_data, &mut slot);
+
+        let file = self.file_handle.as_ref().unwrap();
+        file.write_all_at(&slot, offset as u64)?;
         Ok(())
     }
 
-    fn read_in_memory(&mut self, p_id: usize, p_data: &mut Vec<u8>) -> Result<(), std::io::Error> {
-        let pages = match &mut self.in_memory_pages {
+    /// fill_slot copies a page in to a zeroed slot and stamps its
+    /// trailer.
+    fn fill_slot(&self, p_id: usize, p_data: &[u8], slot: &mut [u8]) {
+        slot[..p_data.len()].copy_from_slice(p_data);
+        let checksum = page_checksum(p_id, &slot[..self.page_size]);
+        slot[self.page_size..].copy_from_slice(&checksum.to_le_bytes());
+    }
+
+    fn read_in_memory(&self, p_id: usize, p_data: &mut [u8]) -> Result<()> {
+        let offset = self.page_offset(p_id, p_data.len())?;
+        let pages = match &self.in_memory_pages {
             None => {
-                return Err(std::io::Error::new(
+                return Err(Error::Io(std::io::Error::new(
                     std::io::ErrorKind::InvalidInput,
                     "no pages available",
-                ));
+                )));
             }
-            Some(pages) => pages,
+            Some(pages) => pages.read()?,
         };
 
-        let offset = (p_id - 1) * self.page_size;
-        p_data.copy_from_slice(&pages[offset..self.page_size]);
+        if offset + p_data.len() > pages.len() {
+            return Err(Error::PageOutOfRange { page_id: p_id });
+        }
+
+        p_data.copy_from_slice(&pages[offset..offset + p_data.len()]);
         Ok(())
     }
 
-    fn read_disk(&self, p_id: usize, p_data: &mut Vec<u8>) -> Result<(), std::io::Error> {
-        let mut buf_reader = BufReader::new(self.file_handle.as_ref().unwrap());
-        buf_reader.seek(SeekFrom::Start(((p_id - 1) * self.page_size) as u64))?;
-        buf_reader.read_exact(p_data)?;
+    fn read_disk(&self, p_id: usize, p_data: &mut [u8]) -> Result<()> {
+        self.page_offset(p_id, p_data.len())?;
+        let slot = self.read_slot(p_id)?;
 
+        let len = p_data.len();
+        p_data.copy_from_slice(&slot[..len]);
         Ok(())
     }
 
-    fn open_data_file(pd: &PathBuf) -> Result<File, std::io::Error> {
+    /// read_slot reads a whole slot, including the header slot, and
+    /// returns the verified page without its trailer.
+    fn read_slot(&self, p_id: usize) -> Result<Vec<u8>> {
+        let mut slot = vec![0; self.slot_size()];
+        let file = self.file_handle.as_ref().unwrap();
+        file.read_exact_at(&mut slot, (p_id * self.slot_size()) as u64)?;
+
+        if !self.verify_slot(p_id, &slot) {
+            return Err(Error::Corruption { page_id: p_id });
+        }
+
+        slot.truncate(self.page_size);
+        Ok(slot)
+    }
+
+    /// verify_slot checks a page against its trailer. Slots that were
+    /// allocated but never written are all zeroes and are accepted.
+    fn verify_slot(&self, p_id: usize, slot: &[u8]) -> bool {
+        let (page, trailer) = slot.split_at(self.page_size);
+        let stored = read_trailer(trailer);
+        if stored == 0 && page.iter().all(|b| *b == 0) {
+            return true;
+        }
+
+        stored == page_checksum(p_id, page)
+    }
+
+    /// slot_size is the number of bytes a page takes up in the
+    /// backing store, including its trailer on disk.
+    fn slot_size(&self) -> usize {
+        if self.in_memory {
+            self.page_size
+        } else {
+            self.page_size + PAGE_TRAILER_SIZE
+        }
+    }
+
+    /// check_page_size makes sure a page fits the superblock with room
+    /// to spare for the free map, and a free map overflow page fits
+    /// its link.
+    fn check_page_size(page_size: usize) -> Result<()> {
+        let min = Self::header_len() + FREE_MAP_LINK_SIZE;
+        if page_size < min {
+            return Err(Error::PageSizeTooSmall { page_size, min });
+        }
+        Ok(())
+    }
+
+    fn header_len() -> usize {
+        bincode::serialized_size(&Superblock::new(0, 0, 0, 0)).expect("superblock has a fixed size")
+            as usize
+    }
+
+    /// page_offset returns the byte offset of a page. Page ids
+    /// start at 1 and a buffer may never spill into the next page.
+    /// Data files keep their superblock in the slot before page 1.
+    fn page_offset(&self, p_id: usize, len: usize) -> Result<usize> {
+        if p_id == 0 {
+            return Err(Error::PageOutOfRange { page_id: p_id });
+        }
+        if len > self.page_size {
+            return Err(Error::Io(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "buffer is larger than a page",
+            )));
+        }
+
+        if self.in_memory {
+            Ok((p_id - 1) * self.page_size)
+        } else {
+            Ok(p_id * self.slot_size())
+        }
+    }
+
+    fn write_header(&self, page_map: &PageMap) -> Result<()> {
+        let free_map_page = page_map.free_map_pages.first().copied().unwrap_or(0);
+        let header = Superblock::new(
+            self.page_size,
+            page_map.page_count,
+            free_map_page,
+            self.checkpoint.load(Ordering::Relaxed),
+        );
+        let mut header = bincode::serialize(&header)
+            .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidData, err))?;
+
+        let header_capacity = self.page_size - header.len();
+        header.extend_from_slice(&page_map.free_map[..header_capacity]);
+        self.write_slot(0, &header)
+    }
+
+    fn open_data_file(pd: &PathBuf) -> std::io::Result<File> {
         OpenOptions::new()
             .read(true)
             .write(true)
@@ -180,38 +736,82 @@ impl DiskManager {
             .open(pd)
     }
 
-    fn page_file_size(&self) -> u64 {
+    pub fn page_dir(&self) -> Option<&PathBuf> {
+        self.page_dir.as_ref()
+    }
+
+    pub fn page_file_size(&self) -> Result<u64> {
         if let Some(file) = self.file_handle.as_ref() {
-            file.metadata().unwrap().len()
+            Ok(file.metadata()?.len())
+        } else if let Some(pages) = self.in_memory_pages.as_ref() {
+            Ok(pages.read()?.len() as u64)
         } else {
-            panic!("no file handle open")
+            Err(Error::Io(std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                "no file handle open",
+            )))
         }
     }
 }
 
+/// page_checksum mixes the page id in to the checksum so a page
+/// written to the wrong slot is caught as well as a damaged one.
+fn page_checksum(p_id: usize, page: &[u8]) -> u32 {
+    let mut crc = Crc32c::new();
+    crc.update(page);
+    crc.update(&(p_id as u64).to_le_bytes());
+    crc.finish()
+}
+
+fn read_link(page: &[u8]) -> usize {
+    let mut bytes = [0; FREE_MAP_LINK_SIZE];
+    bytes.copy_from_slice(&page[..FREE_MAP_LINK_SIZE]);
+    u64::from_le_bytes(bytes) as usize
+}
+
+fn read_trailer(trailer: &[u8]) -> u32 {
+    let mut bytes = [0; PAGE_TRAILER_SIZE];
+    bytes.copy_from_slice(&trailer[..PAGE_TRAILER_SIZE]);
+    u32::from_le_bytes(bytes)
+}
+
+#[cfg(test)]
 mod tests {
     use super::*;
+    use std::io::{BufReader, Read, Seek, SeekFrom, Write};
     use tempdir::TempDir;
     #[test]
     fn test_disk_manager_in_memory() {
-        let mut dm = DiskManager::default();
+        let dm = DiskManager::default();
         assert_eq!(dm.page_size, DEFAULT_PAGE_SIZE);
         assert_eq!(dm.page_dir, None);
         assert!(dm.in_memory);
-        assert_eq!(dm.in_memory_pages, Some(vec![]));
+        assert_eq!(
+            *dm.in_memory_pages.as_ref().unwrap().read().unwrap(),
+            vec![]
+        );
 
         let v_test: Vec<u8> = vec![0; DEFAULT_PAGE_SIZE];
 
-        dm.increase_pages(1);
-        assert_eq!(dm.in_memory_pages, Some(v_test));
+        dm.increase_pages(1).unwrap();
+        assert_eq!(
+            *dm.in_memory_pages.as_ref().unwrap().read().unwrap(),
+            v_test
+        );
 
-        let mut v_test: Vec<u8> = vec![u8::try_from('a').unwrap(); DEFAULT_PAGE_SIZE];
-        dm.write_in_memory(1, &mut v_test).unwrap();
-        assert_eq!(dm.in_memory_pages, Some(v_test));
+        let v_test: Vec<u8> = vec![u8::try_from('a').unwrap(); DEFAULT_PAGE_SIZE];
+        dm.write_in_memory(1, &v_test).unwrap();
+        assert_eq!(
+            *dm.in_memory_pages.as_ref().unwrap().read().unwrap(),
+            v_test
+        );
 
         let mut v_test: Vec<u8> = vec![0; DEFAULT_PAGE_SIZE];
         dm.read_in_memory(1, &mut v_test).unwrap();
-        assert_eq!(dm.in_memory_pages, Some(v_test));
+        assert_eq!(
+            *dm.in_memory_pages.as_ref().unwrap().read().unwrap(),
+            v_test
+        );
     }
 
     #[test]
@@ -220,27 +820,27 @@ mod tests {
         let temp_file = temp_dir.path().join("test.db");
         let (tx, rx) = std::sync::mpsc::channel();
 
-        let mut dm = DiskManager::new(DEFAULT_PAGE_SIZE, Some(temp_file), false);
+        let dm = DiskManager::new(DEFAULT_PAGE_SIZE, Some(temp_file), false).unwrap();
         assert_eq!(dm.page_size, DEFAULT_PAGE_SIZE);
         assert!(!dm.in_memory);
 
         // Blank page
         let mut v_test: Vec<u8> = vec![0; DEFAULT_PAGE_SIZE];
-        dm.increase_pages(1);
+        dm.increase_pages(1).unwrap();
         dm.read_page(&mut v_test, 1, tx.clone());
 
         let recv = rx.recv().unwrap();
-        assert!(recv);
+        assert!(recv.is_ok());
         assert_eq!(v_test, vec![0; DEFAULT_PAGE_SIZE]);
 
-        let mut data = vec![u8::try_from('a').unwrap(); DEFAULT_PAGE_SIZE];
-        dm.write_page(&mut data, 1, tx.clone());
+        let data = vec![u8::try_from('a').unwrap(); DEFAULT_PAGE_SIZE];
+        dm.write_page(&data, 1, tx.clone());
         let recv = rx.recv().unwrap();
-        assert!(recv);
+        assert!(recv.is_ok());
 
         dm.read_page(&mut v_test, 1, tx.clone());
         let recv = rx.recv().unwrap();
-        assert!(recv);
+        assert!(recv.is_ok());
 
         assert_eq!(v_test, data);
 
@@ -254,38 +854,44 @@ mod tests {
         let temp_file = temp_dir.path().join("test.db");
         let (tx, rx) = std::sync::mpsc::channel();
 
-        let mut dm = DiskManager::new(DEFAULT_PAGE_SIZE, Some(temp_file), false);
+        let dm = DiskManager::new(DEFAULT_PAGE_SIZE, Some(temp_file), false).unwrap();
         assert_eq!(dm.page_size, DEFAULT_PAGE_SIZE);
         assert!(!dm.in_memory);
 
         // Create 10 pages on disk
         for i in 1..11 {
             let mut v_test: Vec<u8> = vec![0; DEFAULT_PAGE_SIZE];
-            dm.increase_pages(i);
+            dm.increase_pages(i).unwrap();
             dm.read_page(&mut v_test, i, tx.clone());
 
             let recv = rx.recv().unwrap();
 
-            assert!(recv);
+            assert!(recv.is_ok());
             assert_eq!(v_test, vec![0; DEFAULT_PAGE_SIZE]);
         }
 
-        assert_eq!(dm.page_file_size(), (DEFAULT_PAGE_SIZE * 10) as u64);
+        assert_eq!(
+            dm.page_file_size().unwrap(),
+            ((DEFAULT_PAGE_SIZE + PAGE_TRAILER_SIZE) * 11) as u64
+        );
 
         for i in 1..11 {
-            let mut v_test: Vec<u8> = "foo".as_bytes().to_vec();
-            dm.write_page(&mut v_test, i, tx.clone());
+            let v_test: Vec<u8> = "foo".as_bytes().to_vec();
+            dm.write_page(&v_test, i, tx.clone());
             let recv = rx.recv().unwrap();
-            assert!(recv);
+            assert!(recv.is_ok());
         }
 
-        assert_eq!(dm.page_file_size(), (DEFAULT_PAGE_SIZE * 10) as u64);
+        assert_eq!(
+            dm.page_file_size().unwrap(),
+            ((DEFAULT_PAGE_SIZE + PAGE_TRAILER_SIZE) * 11) as u64
+        );
 
         for i in 1..11 {
             let mut v_test: Vec<u8> = vec![0; DEFAULT_PAGE_SIZE];
             dm.read_page(&mut v_test, i, tx.clone());
             let recv = rx.recv().unwrap();
-            assert!(recv);
+            assert!(recv.is_ok());
 
             assert_eq!(
                 String::from_utf8(v_test).unwrap().trim_end_matches('\0'),
@@ -296,4 +902,261 @@ mod tests {
         drop(dm);
         temp_dir.close().unwrap();
     }
+
+    #[test]
+    fn test_disk_manager_reopen() {
+        let temp_dir = TempDir::new("test_disk_manager").unwrap();
+        let temp_file = temp_dir.path().join("test.db");
+        let (tx, rx) = std::sync::mpsc::channel();
+
+        let dm = DiskManager::new(DEFAULT_PAGE_SIZE, Some(temp_file.clone()), false).unwrap();
+        for i in 1..6 {
+            dm.increase_pages(i).unwrap();
+            dm.write_page(format!("page-{}", i).as_bytes(), i, tx.clone());
+            assert!(rx.recv().unwrap().is_ok());
+        }
+        assert_eq!(dm.last_checkpoint(), 0);
+        assert_eq!(dm.record_checkpoint().unwrap(), 1);
+        assert_eq!(dm.record_checkpoint().unwrap(), 2);
+        drop(dm);
+
+        let dm = DiskManager::open(DEFAULT_PAGE_SIZE, temp_file).unwrap();
+        assert_eq!(dm.page_count(), 5);
+        assert_eq!(dm.last_checkpoint(), 2);
+
+        for i in 1..6 {
+            let mut v_test: Vec<u8> = vec![0; DEFAULT_PAGE_SIZE];
+            dm.read_page(&mut v_test, i, tx.clone());
+            assert!(rx.recv().unwrap().is_ok());
+
+            assert_eq!(
+                String::from_utf8(v_test).unwrap().trim_end_matches('\0'),
+                format!("page-{}", i)
+            );
+        }
+
+        drop(dm);
+        temp_dir.close().unwrap();
+    }
+
+    #[test]
+    fn test_disk_manager_open_invalid_header() {
+        let temp_dir = TempDir::new("test_disk_manager").unwrap();
+        let temp_file = temp_dir.path().join("test.db");
+
+        let dm = DiskManager::new(DEFAULT_PAGE_SIZE, Some(temp_file.clone()), false).unwrap();
+        dm.increase_pages(2).unwrap();
+        drop(dm);
+
+        match DiskManager::open(DEFAULT_PAGE_SIZE * 2, temp_file.clone()) {
+            Err(Error::PageSizeMismatch { expected, found }) => {
+                assert_eq!(expected, DEFAULT_PAGE_SIZE * 2);
+                assert_eq!(found, DEFAULT_PAGE_SIZE);
+            }
+            _ => panic!("expected a page size mismatch"),
+        }
+
+        // Losing the tail of the file leaves the header pointing past the end
+        let file = OpenOptions::new().write(true).open(&temp_file).unwrap();
+        file.set_len(((DEFAULT_PAGE_SIZE + PAGE_TRAILER_SIZE) * 2) as u64)
+            .unwrap();
+        match DiskManager::open(DEFAULT_PAGE_SIZE, temp_file.clone()) {
+            Err(Error::PageCountMismatch { header, file }) => {
+                assert_eq!(header, 2);
+                assert_eq!(file, 1);
+            }
+            _ => panic!("expected a page count mismatch"),
+        }
+
+        std::fs::write(&temp_file, vec![0xff; DEFAULT_PAGE_SIZE]).unwrap();
+        assert!(matches!(
+            DiskManager::open(DEFAULT_PAGE_SIZE, temp_file.clone()),
+            Err(Error::InvalidMagic(_))
+        ));
+
+        assert!(matches!(
+            DiskManager::open(DEFAULT_PAGE_SIZE, temp_dir.path().join("missing.db")),
+            Err(Error::Io(_))
+        ));
+
+        temp_dir.close().unwrap();
+    }
+
+    #[test]
+    fn test_disk_manager_checksums() {
+        let temp_dir = TempDir::new("test_disk_manager").unwrap();
+        let temp_file = temp_dir.path().join("test.db");
+        let (tx, rx) = std::sync::mpsc::channel();
+        let slot_size = DEFAULT_PAGE_SIZE + PAGE_TRAILER_SIZE;
+
+        let dm = DiskManager::new(DEFAULT_PAGE_SIZE, Some(temp_file.clone()), false).unwrap();
+        dm.increase_pages(4).unwrap();
+        for i in 1..4 {
+            dm.write_page(&[i as u8; DEFAULT_PAGE_SIZE], i, tx.clone());
+            assert!(rx.recv().unwrap().is_ok());
+        }
+
+        // Page 4 was never written, blank pages must still verify
+        assert_eq!(dm.verify().unwrap(), Vec::<usize>::new());
+
+        // Flip a byte in the middle of page 2
+        let mut file = OpenOptions::new().write(true).open(&temp_file).unwrap();
+        file.seek(SeekFrom::Start((slot_size * 2 + 100) as u64))
+            .unwrap();
+        file.write_all(&[0xaa]).unwrap();
+
+        let mut v_test = vec![0; DEFAULT_PAGE_SIZE];
+        dm.read_page(&mut v_test, 2, tx.clone());
+        match rx.recv().unwrap() {
+            Err(Error::Corruption { page_id }) => assert_eq!(page_id, 2),
+            _ => panic!("expected a checksum mismatch"),
+        }
+
+        // A page copied in to another slot fails because the id is mixed in
+        let mut page_one = vec![0; slot_size];
+        let mut reader = BufReader::new(File::open(&temp_file).unwrap());
+        reader.seek(SeekFrom::Start(slot_size as u64)).unwrap();
+        reader.read_exact(&mut page_one).unwrap();
+        file.seek(SeekFrom::Start((slot_size * 3) as u64)).unwrap();
+        file.write_all(&page_one).unwrap();
+
+        assert_eq!(dm.verify().unwrap(), vec![2, 3]);
+
+        dm.read_page(&mut v_test, 1, tx.clone());
+        assert!(rx.recv().unwrap().is_ok());
+        assert_eq!(v_test, vec![1; DEFAULT_PAGE_SIZE]);
+
+        drop(dm);
+        temp_dir.close().unwrap();
+    }
+
+    #[test]
+    fn test_disk_manager_vectored() {
+        let temp_dir = TempDir::new("test_disk_manager").unwrap();
+        let temp_file = temp_dir.path().join("test.db");
+        let slot_size = DEFAULT_PAGE_SIZE + PAGE_TRAILER_SIZE;
+
+        let dm = DiskManager::new(DEFAULT_PAGE_SIZE, Some(temp_file.clone()), false).unwrap();
+        dm.increase_pages(8).unwrap();
+        let pages = (3..7)
+            .map(|i| vec![i as u8; DEFAULT_PAGE_SIZE])
+            .collect::<Vec<_>>();
+        let slices = pages.iter().map(|p| p.as_slice()).collect::<Vec<_>>();
+        dm.write_pages(3, &slices).unwrap();
+
+        // Each page in the run gets its own trailer
+        assert_eq!(dm.verify().unwrap(), Vec::<usize>::new());
+        let (tx, rx) = std::sync::mpsc::channel();
+        let mut v_test = vec![0; DEFAULT_PAGE_SIZE];
+        dm.read_page(&mut v_test, 5, tx.clone());
+        rx.recv().unwrap().unwrap();
+        assert_eq!(v_test, vec![5; DEFAULT_PAGE_SIZE]);
+
+        let mut reads = vec![vec![0; DEFAULT_PAGE_SIZE]; 4];
+        let mut slices = reads
+            .iter_mut()
+            .map(|p| p.as_mut_slice())
+            .collect::<Vec<_>>();
+        dm.read_pages(2, &mut slices).unwrap();
+        assert_eq!(reads[0], vec![0; DEFAULT_PAGE_SIZE]);
+        assert_eq!(reads[1..], pages[..3]);
+
+        let mut file = OpenOptions::new().write(true).open(&temp_file).unwrap();
+        file.seek(SeekFrom::Start((slot_size * 4 + 100) as u64))
+            .unwrap();
+        file.write_all(&[0xaa]).unwrap();
+        let mut slices = reads
+            .iter_mut()
+            .map(|p| p.as_mut_slice())
+            .collect::<Vec<_>>();
+        assert!(matches!(
+            dm.read_pages(3, &mut slices),
+            Err(Error::Corruption { page_id: 4 })
+        ));
+
+        drop(dm);
+        temp_dir.close().unwrap();
+    }
+
+    #[test]
+    fn test_disk_manager_free_map() {
+        let temp_dir = TempDir::new("test_disk_manager").unwrap();
+        let temp_file = temp_dir.path().join("test.db");
+
+        let dm = DiskManager::new(DEFAULT_PAGE_SIZE, Some(temp_file.clone()), false).unwrap();
+        for i in 1..6 {
+            assert_eq!(dm.allocate_page().unwrap(), i);
+        }
+
+        dm.decrease_pages(2).unwrap();
+        dm.decrease_pages(4).unwrap();
+        assert!(matches!(
+            dm.decrease_pages(4),
+            Err(Error::PageNotAllocated { page_id: 4 })
+        ));
+        assert!(matches!(
+            dm.decrease_pages(6),
+            Err(Error::PageOutOfRange { page_id: 6 })
+        ));
+        drop(dm);
+
+        // The free map survives a restart
+        let dm = DiskManager::open(DEFAULT_PAGE_SIZE, temp_file).unwrap();
+        assert!(dm.is_free(2));
+        assert!(dm.is_free(4));
+        assert_eq!(dm.allocate_page().unwrap(), 2);
+        assert_eq!(dm.allocate_page().unwrap(), 4);
+        assert_eq!(dm.allocate_page().unwrap(), 6);
+        assert_eq!(dm.verify().unwrap(), Vec::<usize>::new());
+
+        drop(dm);
+        temp_dir.close().unwrap();
+    }
+
+    #[test]
+    fn test_disk_manager_free_map_overflow() {
+        let temp_dir = TempDir::new("test_disk_manager").unwrap();
+        let temp_file = temp_dir.path().join("test.db");
+        let page_size = 64;
+
+        assert!(matches!(
+            DiskManager::new(16, Some(temp_file.clone()), false),
+            Err(Error::PageSizeTooSmall { page_size: 16, .. })
+        ));
+
+        // The header only has room to track a few hundred pages here
+        let dm = DiskManager::new(page_size, Some(temp_file.clone()), false).unwrap();
+        dm.increase_pages(700).unwrap();
+        dm.decrease_pages(3).unwrap();
+        dm.decrease_pages(699).unwrap();
+
+        // Two overflow pages get appended to the end of the file
+        assert_eq!(dm.page_count(), 702);
+        assert_eq!(dm.page_map.lock().unwrap().free_map_pages, vec![701, 702]);
+        assert!(matches!(
+            dm.decrease_pages(701),
+            Err(Error::PageNotAllocated { page_id: 701 })
+        ));
+        drop(dm);
+
+        let dm = DiskManager::open(page_size, temp_file.clone()).unwrap();
+        assert_eq!(dm.page_map.lock().unwrap().free_map_pages, vec![701, 702]);
+        assert!(!dm.is_allocated(702));
+        assert_eq!(dm.allocate_page().unwrap(), 3);
+        assert_eq!(dm.allocate_page().unwrap(), 699);
+        assert_eq!(dm.allocate_page().unwrap(), 703);
+        assert_eq!(dm.verify().unwrap(), Vec::<usize>::new());
+
+        // A bad link is reported against the page it was read from
+        let mut page = vec![0; page_size];
+        page[..FREE_MAP_LINK_SIZE].copy_from_slice(&u64::MAX.to_le_bytes());
+        dm.write_slot(701, &page).unwrap();
+        drop(dm);
+        assert!(matches!(
+            DiskManager::open(page_size, temp_file),
+            Err(Error::Corruption { page_id: 701 })
+        ));
+
+        temp_dir.close().unwrap();
+    }
 }
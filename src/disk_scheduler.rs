@@ -1,99 +1,351 @@
-use crate::disk_manager::{DiskManager, DiskManagerRequest};
-use std::sync::mpsc::{Receiver, Sender, TryRecvError};
-use std::sync::{mpsc, Arc, Mutex};
+use crate::disk_manager::{DiskManager, DiskManagerRequest, IoPriority};
+use crate::error::{Error, Result};
+use crate::io_future::{Completion, IoFuture};
+use crate::request_queue::RequestQueue;
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex, Weak};
+use std::thread::JoinHandle;
+
+/// Number of IO workers used by [DiskScheduler::new].
+pub const DEFAULT_IO_WORKERS: usize = 4;
+
+/// Number of requests that may wait in the queue before submitters
+/// are held back, used by [DiskScheduler::new] and
+/// [DiskScheduler::with_workers].
+pub const DEFAULT_QUEUE_DEPTH: usize = 1024;
+
+/// Most requests for consecutive pages a worker merges in to a
+/// single read or write.
+pub const MAX_COALESCED_PAGES: usize = 32;
 
 /// [DiskScheduler] implements a IO scheduler for reading and writing
-/// from disk in to memory.
+/// from disk in to memory. A pool of workers issues requests against
+/// the [DiskManager] in parallel, requests for the same page are
+/// always completed in the order they were submitted. Queued requests
+/// for consecutive pages are merged in to one read or write.
 pub struct DiskScheduler {
-    /// channel is used a queue for disk reads and writes to be processed
-    sender: Sender<DiskManagerRequest>,
-    disk_manager: Arc<Mutex<DiskManager>>,
+    /// queue holds disk reads and writes waiting to be processed
+    queue: Arc<RequestQueue>,
+    disk_manager: Arc<DiskManager>,
+    /// workers is emptied once the scheduler has shut down
+    workers: Mutex<Vec<JoinHandle<()>>>,
+}
+
+/// [DiskSchedulerStats] is a snapshot of the scheduler's queue.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DiskSchedulerStats {
+    /// queue_depth is the number of requests waiting for a worker.
+    pub queue_depth: usize,
+    /// queue_capacity is the most requests that may wait at once.
+    pub queue_capacity: usize,
+    /// in_flight is the number of pages workers are busy with.
+    pub in_flight: usize,
+}
+
+/// [RequestHandle] refers to a request submitted to a
+/// [DiskScheduler]. Dropping it leaves the request queued.
+pub struct RequestHandle {
+    id: u64,
+    queue: Weak<RequestQueue>,
+}
+
+impl RequestHandle {
+    /// cancel takes the request off the queue if no worker has picked
+    /// it up yet. Its callback is completed with [Error::Cancelled]
+    /// and true is returned. Once the request is running or done it
+    /// can no longer be cancelled and false is returned.
+    pub fn cancel(&self) -> bool {
+        let request = self.queue.upgrade().and_then(|queue| queue.cancel(self.id));
+        match request {
+            Some(DiskManagerRequest::DiskRwRequest { callback, .. }) => {
+                callback.complete(Err(Error::Cancelled));
+                true
+            }
+            None => false,
+        }
+    }
 }
 
 impl DiskScheduler {
-    pub fn new(disk_manager: Arc<Mutex<DiskManager>>) -> Self {
-        let (tx, rx) = mpsc::channel();
-        Self::spawn_worker(rx, disk_manager.clone());
+    pub fn new(disk_manager: Arc<DiskManager>) -> Self {
+        Self::with_workers(disk_manager, DEFAULT_IO_WORKERS)
+    }
+
+    pub fn with_workers(disk_manager: Arc<DiskManager>, workers: usize) -> Self {
+        Self::with_queue_depth(disk_manager, workers, DEFAULT_QUEUE_DEPTH)
+    }
+
+    /// with_queue_depth bounds the number of queued requests. Once
+    /// queue_depth requests are waiting [DiskScheduler::request]
+    /// blocks and [DiskScheduler::try_request] fails.
+    pub fn with_queue_depth(
+        disk_manager: Arc<DiskManager>,
+        workers: usize,
+        queue_depth: usize,
+    ) -> Self {
+        let queue = Arc::new(RequestQueue::new(queue_depth));
+        let workers = (0..workers.max(1))
+            .map(|_| Self::spawn_worker(queue.clone(), disk_manager.clone()))
+            .collect();
 
         Self {
-            sender: tx,
+            queue,
             disk_manager,
+            workers: Mutex::new(workers),
         }
     }
 
-    pub fn spawn_worker(
-        receiver: Receiver<DiskManagerRequest>,
-        disk_manager: Arc<Mutex<DiskManager>>,
-    ) -> std::thread::JoinHandle<()> {
+    fn spawn_worker(queue: Arc<RequestQueue>, disk_manager: Arc<DiskManager>) -> JoinHandle<()> {
         std::thread::spawn(move || loop {
-            match receiver.try_recv() {
-                Ok(req) => match req {
-                    DiskManagerRequest::DiskRwRequest {
-                        is_write,
-                        data,
-                        page_id,
-                        callback,
-                    } => {
-                        let mut data = data.lock().unwrap();
-                        let mut_data = data.as_mut();
-                        let mut dm = disk_manager.lock().unwrap();
-                        if is_write {
-                            dm.write_page(mut_data, page_id, callback);
-                        } else {
-                            dm.read_page(mut_data, page_id, callback);
-                        }
+            let run = queue.pop_run(MAX_COALESCED_PAGES);
+            if run.is_empty() {
+                break;
+            }
+
+            let page_ids = run.iter().map(|req| req.page_id()).collect::<Vec<_>>();
+            Self::execute_run(&disk_manager, run);
+            for page_id in page_ids {
+                queue.complete(page_id);
+            }
+        })
+    }
+
+    /// execute_run issues a run of requests for consecutive pages as
+    /// one operation and completes each callback on its own. If the
+    /// merged operation fails the requests are retried one by one so
+    /// every caller sees the error for its own page.
+    fn execute_run(disk_manager: &DiskManager, run: Vec<DiskManagerRequest>) {
+        let first_page_id = run[0].page_id();
+        let is_write = run[0].is_write();
+        let (data, callbacks): (Vec<_>, Vec<_>) = run
+            .into_iter()
+            .map(|req| match req {
+                DiskManagerRequest::DiskRwRequest { data, callback, .. } => (data, callback),
+            })
+            .unzip();
+
+        if data.len() > 1 {
+            let result = match data
+                .iter()
+                .map(|d| Ok(d.lock()?))
+                .collect::<Result<Vec<_>>>()
+            {
+                Ok(guards) if is_write => {
+                    let pages = guards.iter().map(|g| g.as_slice()).collect::<Vec<_>>();
+                    disk_manager.write_pages(first_page_id, &pages)
+                }
+                Ok(mut guards) => {
+                    let mut pages = guards
+                        .iter_mut()
+                        .map(|g| g.as_mut_slice())
+                        .collect::<Vec<_>>();
+                    disk_manager.read_pages(first_page_id, &mut pages)
+                }
+                Err(err) => Err(err),
+            };
+
+            if result.is_ok() {
+                for callback in callbacks {
+                    callback.complete(Ok(()));
+                }
+                return;
+            }
+        }
+
+        for (i, (data, callback)) in data.into_iter().zip(callbacks).enumerate() {
+            let page_id = first_page_id + i;
+            match data.lock() {
+                Ok(mut data) => {
+                    if is_write {
+                        disk_manager.write_page(&data, page_id, callback);
+                    } else {
+                        disk_manager.read_page(&mut data, page_id, callback);
                     }
-                },
-                Err(TryRecvError::Empty) => {
-                    std::thread::sleep(std::time::Duration::from_millis(10));
                 }
-                Err(TryRecvError::Disconnected) => {
-                    break;
+                Err(_) => {
+                    callback.complete(Err(Error::Poisoned));
                 }
             }
-        })
+        }
+    }
+
+    pub fn new_page(&mut self, size: usize) -> Result<()> {
+        self.disk_manager.increase_pages(size)
+    }
+
+    pub fn allocate_page(&self) -> Result<usize> {
+        self.disk_manager.allocate_page()
+    }
+
+    pub fn deallocate_page(&self, page_id: usize) -> Result<()> {
+        self.disk_manager.decrease_pages(page_id)
+    }
+
+    pub fn page_count(&self) -> Result<usize> {
+        Ok(self.disk_manager.page_count())
+    }
+
+    pub fn is_allocated(&self, page_id: usize) -> bool {
+        self.disk_manager.is_allocated(page_id)
+    }
+
+    pub fn stats(&self) -> Result<DiskSchedulerStats> {
+        self.queue.stats()
+    }
+
+    /// sync flushes the data file to stable storage. Only requests
+    /// that have already completed are covered.
+    pub fn sync(&self) -> Result<()> {
+        self.disk_manager.sync()
     }
 
-    pub fn new_page(&mut self, size: usize) {
-        let mut dm = self.disk_manager.lock().unwrap();
-        dm.increase_pages(size);
+    /// record_checkpoint marks a checkpoint in the data file header
+    /// once every write it covers has completed.
+    pub fn record_checkpoint(&self) -> Result<u64> {
+        self.disk_manager.record_checkpoint()
     }
 
+    /// request queues a foreground read or write, waiting for room
+    /// in the queue if it is full.
     pub fn request(
-        &mut self,
+        &self,
         is_write: bool,
         data: Arc<Mutex<Vec<u8>>>,
         page_id: usize,
-        callback: Sender<bool>,
-    ) -> Result<(), mpsc::SendError<DiskManagerRequest>> {
-        self.sender.send(DiskManagerRequest::DiskRwRequest {
+        callback: Sender<Result<()>>,
+    ) -> Result<RequestHandle> {
+        let priority = if is_write {
+            IoPriority::ForegroundWrite
+        } else {
+            IoPriority::ForegroundRead
+        };
+
+        self.request_with_priority(priority, is_write, data, page_id, callback)
+    }
+
+    /// submit queues a read or write and returns a future that
+    /// resolves once a worker has completed it. Like
+    /// [DiskScheduler::request] it waits for room in the queue.
+    pub fn submit(
+        &self,
+        priority: IoPriority,
+        is_write: bool,
+        data: Arc<Mutex<Vec<u8>>>,
+        page_id: usize,
+    ) -> Result<IoFuture> {
+        let (callback, future) = Completion::future();
+        self.queue.push(DiskManagerRequest::DiskRwRequest {
             is_write,
             data,
             page_id,
+            priority,
             callback,
-        })
+        })?;
+
+        Ok(future)
+    }
+
+    /// try_request is [DiskScheduler::request] without waiting. It
+    /// fails with [Error::QueueFull] when the queue has no room.
+    pub fn try_request(
+        &self,
+        is_write: bool,
+        data: Arc<Mutex<Vec<u8>>>,
+        page_id: usize,
+        callback: Sender<Result<()>>,
+    ) -> Result<RequestHandle> {
+        let priority = if is_write {
+            IoPriority::ForegroundWrite
+        } else {
+            IoPriority::ForegroundRead
+        };
+
+        let id = self.queue.try_push(DiskManagerRequest::DiskRwRequest {
+            is_write,
+            data,
+            page_id,
+            priority,
+            callback: callback.into(),
+        })?;
+
+        Ok(self.handle(id))
+    }
+
+    pub fn request_with_priority(
+        &self,
+        priority: IoPriority,
+        is_write: bool,
+        data: Arc<Mutex<Vec<u8>>>,
+        page_id: usize,
+        callback: Sender<Result<()>>,
+    ) -> Result<RequestHandle> {
+        let id = self.queue.push(DiskManagerRequest::DiskRwRequest {
+            is_write,
+            data,
+            page_id,
+            priority,
+            callback: callback.into(),
+        })?;
+
+        Ok(self.handle(id))
+    }
+
+    fn handle(&self, id: u64) -> RequestHandle {
+        RequestHandle {
+            id,
+            queue: Arc::downgrade(&self.queue),
+        }
+    }
+
+    /// shutdown stops accepting requests, waits for the workers to
+    /// finish everything already queued and then syncs the data file.
+    /// Once shut down every new request fails with
+    /// [Error::Disconnected]. Calling it again only repeats the sync.
+    pub fn shutdown(&self) -> Result<()> {
+        self.queue.close();
+
+        let workers = std::mem::take(&mut *self.workers.lock()?);
+        let mut result = Ok(());
+        for worker in workers {
+            if worker.join().is_err() {
+                result = Err(Error::Disconnected);
+            }
+        }
+
+        result.and(self.disk_manager.sync())
+    }
+}
+
+impl Drop for DiskScheduler {
+    fn drop(&mut self) {
+        if let Err(err) = self.shutdown() {
+            eprintln!("Error occurred during drop: {}", err);
+        }
     }
 }
 
+#[cfg(test)]
 mod tests {
     use super::*;
+    use std::sync::mpsc;
+    use std::time::{Duration, Instant};
     #[test]
     fn test_disk_scheduler() {
         let (call_tx, call_rx) = mpsc::channel();
-        let dm = Arc::new(Mutex::new(DiskManager::new(4096, None, true)));
+        let dm = Arc::new(DiskManager::new(4096, None, true).unwrap());
         let mut ds = DiskScheduler::new(dm);
 
         let b = std::thread::spawn(move || {
             for i in 1..10 {
                 let v = Arc::new(Mutex::new(vec![0; 1024]));
-                ds.new_page(i);
+                ds.new_page(i).unwrap();
                 ds.request(false, v, i, call_tx.clone()).unwrap();
             }
         });
 
         let a = std::thread::spawn(move || {
             while let Ok(val) = call_rx.recv() {
-                assert!(val);
+                assert!(val.is_ok());
                 println!("ok");
             }
         });
@@ -101,4 +353,302 @@ mod tests {
         b.join().unwrap();
         a.join().unwrap();
     }
+
+    #[test]
+    fn test_disk_scheduler_latency() {
+        let (call_tx, call_rx) = mpsc::channel();
+        let dm = Arc::new(DiskManager::new(4096, None, true).unwrap());
+        let mut ds = DiskScheduler::new(dm);
+        ds.new_page(1).unwrap();
+
+        let v = Arc::new(Mutex::new(vec![0; 4096]));
+        let requests = 100;
+        let start = Instant::now();
+        for _ in 0..requests {
+            // The worker goes idle between requests so each one has to wake it
+            ds.request(false, v.clone(), 1, call_tx.clone()).unwrap();
+            call_rx.recv().unwrap().unwrap();
+        }
+
+        let average = start.elapsed() / requests;
+        assert!(
+            average < Duration::from_millis(1),
+            "average turnaround was {:?}",
+            average
+        );
+    }
+
+    #[test]
+    fn test_disk_scheduler_same_page_order() {
+        let (call_tx, call_rx) = mpsc::channel();
+        let dm = Arc::new(DiskManager::new(4096, None, true).unwrap());
+        let mut ds = DiskScheduler::with_workers(dm, 8);
+        ds.new_page(2).unwrap();
+
+        // Writes to page 1 queue up behind each other while page 2
+        // is free to be read by any worker in between
+        for i in 0..200_u8 {
+            let v = Arc::new(Mutex::new(vec![i; 4096]));
+            ds.request(true, v, 1, call_tx.clone()).unwrap();
+            let v = Arc::new(Mutex::new(vec![0; 4096]));
+            ds.request(false, v, 2, call_tx.clone()).unwrap();
+        }
+
+        let v = Arc::new(Mutex::new(vec![0; 4096]));
+        ds.request(false, v.clone(), 1, call_tx.clone()).unwrap();
+        for _ in 0..401 {
+            call_rx.recv().unwrap().unwrap();
+        }
+
+        assert_eq!(*v.lock().unwrap(), vec![199; 4096]);
+    }
+
+    #[test]
+    fn test_disk_scheduler_parallel_io() {
+        let temp_dir = tempdir::TempDir::new("test_disk_scheduler").unwrap();
+        let temp_file = temp_dir.path().join("test.db");
+        let dm = Arc::new(DiskManager::new(4096, Some(temp_file), false).unwrap());
+        let mut ds = DiskScheduler::with_workers(dm, 4);
+        ds.new_page(64).unwrap();
+        let ds = Arc::new(ds);
+
+        let writers = (0..8)
+            .map(|t| {
+                let ds = ds.clone();
+                std::thread::spawn(move || {
+                    let (call_tx, call_rx) = mpsc::channel();
+                    for page_id in (1..=64).filter(|p| p % 8 == t) {
+                        let v = Arc::new(Mutex::new(vec![page_id as u8; 4096]));
+                        ds.request(true, v, page_id, call_tx.clone()).unwrap();
+                    }
+                    for _ in 0..8 {
+                        call_rx.recv().unwrap().unwrap();
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        for writer in writers {
+            writer.join().unwrap();
+        }
+
+        let (call_tx, call_rx) = mpsc::channel();
+        for page_id in 1..=64 {
+            let v = Arc::new(Mutex::new(vec![0; 4096]));
+            ds.request(false, v.clone(), page_id, call_tx.clone())
+                .unwrap();
+            call_rx.recv().unwrap().unwrap();
+            assert_eq!(*v.lock().unwrap(), vec![page_id as u8; 4096]);
+        }
+
+        drop(ds);
+        temp_dir.close().unwrap();
+    }
+
+    #[test]
+    fn test_disk_scheduler_coalesced_errors() {
+        let dm = Arc::new(DiskManager::new(4096, None, true).unwrap());
+        let mut ds = DiskScheduler::with_workers(dm, 1);
+        ds.new_page(4).unwrap();
+
+        // Page 5 is out of range, only its own request may fail
+        let callbacks = (2..=5)
+            .map(|page_id| {
+                let (call_tx, call_rx) = mpsc::channel();
+                let v = Arc::new(Mutex::new(vec![page_id as u8; 4096]));
+                ds.request(true, v, page_id, call_tx).unwrap();
+                (page_id, call_rx)
+            })
+            .collect::<Vec<_>>();
+        for (page_id, call_rx) in callbacks {
+            let result = call_rx.recv().unwrap();
+            if page_id == 5 {
+                assert!(matches!(result, Err(Error::PageOutOfRange { page_id: 5 })));
+            } else {
+                assert!(result.is_ok());
+            }
+        }
+
+        let (call_tx, call_rx) = mpsc::channel();
+        let v = Arc::new(Mutex::new(vec![0; 4096]));
+        ds.request(false, v.clone(), 4, call_tx).unwrap();
+        call_rx.recv().unwrap().unwrap();
+        assert_eq!(*v.lock().unwrap(), vec![4; 4096]);
+    }
+
+    #[test]
+    fn test_disk_scheduler_shared_buffer() {
+        let dm = Arc::new(DiskManager::new(4096, None, true).unwrap());
+        let mut ds = DiskScheduler::with_workers(dm, 1);
+        ds.new_page(9).unwrap();
+
+        // Hold the only worker up so the reads are queued together
+        let (call_tx, call_rx) = mpsc::channel();
+        let blocker = Arc::new(Mutex::new(vec![0; 4096]));
+        let held = blocker.lock().unwrap();
+        ds.request(true, blocker.clone(), 8, call_tx).unwrap();
+        while ds.stats().unwrap().in_flight == 0 {
+            std::thread::yield_now();
+        }
+
+        // Pages 1 and 2 read into the same buffer can't share a run
+        let v = Arc::new(Mutex::new(vec![7; 4096]));
+        let callbacks = [1, 2].map(|page_id| {
+            let (call_tx, call_rx) = mpsc::channel();
+            ds.request(false, v.clone(), page_id, call_tx).unwrap();
+            call_rx
+        });
+        drop(held);
+        call_rx.recv().unwrap().unwrap();
+        for call_rx in callbacks {
+            call_rx
+                .recv_timeout(Duration::from_secs(10))
+                .unwrap()
+                .unwrap();
+        }
+        assert_eq!(*v.lock().unwrap(), vec![0; 4096]);
+    }
+
+    #[test]
+    fn test_disk_scheduler_shutdown() {
+        let temp_dir = tempdir::TempDir::new("test_disk_scheduler").unwrap();
+        let temp_file = temp_dir.path().join("test.db");
+        let dm = Arc::new(DiskManager::new(4096, Some(temp_file.clone()), false).unwrap());
+        let mut ds = DiskScheduler::with_workers(dm.clone(), 2);
+        ds.new_page(16).unwrap();
+
+        // Nobody waits on the callbacks, shutdown has to drain the queue
+        let (call_tx, call_rx) = mpsc::channel();
+        for page_id in 1..=16 {
+            let v = Arc::new(Mutex::new(vec![page_id as u8; 4096]));
+            ds.request(true, v, page_id, call_tx.clone()).unwrap();
+        }
+        ds.shutdown().unwrap();
+        for _ in 1..=16 {
+            call_rx.try_recv().unwrap().unwrap();
+        }
+
+        let v = Arc::new(Mutex::new(vec![0; 4096]));
+        assert!(matches!(
+            ds.request(false, v, 1, call_tx),
+            Err(Error::Disconnected)
+        ));
+        ds.shutdown().unwrap();
+        drop(ds);
+        drop(dm);
+
+        let dm = DiskManager::open(4096, temp_file).unwrap();
+        let (call_tx, call_rx) = mpsc::channel();
+        for page_id in 1..=16 {
+            let mut v = vec![0; 4096];
+            dm.read_page(&mut v, page_id, call_tx.clone());
+            call_rx.recv().unwrap().unwrap();
+            assert_eq!(v, vec![page_id as u8; 4096]);
+        }
+
+        temp_dir.close().unwrap();
+    }
+
+    #[test]
+    fn test_disk_scheduler_backpressure() {
+        let dm = Arc::new(DiskManager::new(4096, None, true).unwrap());
+        let mut ds = DiskScheduler::with_queue_depth(dm, 1, 4);
+        ds.new_page(64).unwrap();
+
+        // Hold the worker up on the first page so the queue fills
+        let (call_tx, call_rx) = mpsc::channel();
+        let blocker = Arc::new(Mutex::new(vec![0; 4096]));
+        let held = blocker.lock().unwrap();
+        ds.request(true, blocker.clone(), 1, call_tx.clone())
+            .unwrap();
+        while ds.stats().unwrap().in_flight == 0 {
+            std::thread::yield_now();
+        }
+
+        for page_id in (3..11).step_by(2) {
+            let v = Arc::new(Mutex::new(vec![0; 4096]));
+            ds.try_request(true, v, page_id, call_tx.clone()).unwrap();
+        }
+        let v = Arc::new(Mutex::new(vec![0; 4096]));
+        assert!(matches!(
+            ds.try_request(true, v, 20, call_tx.clone()),
+            Err(Error::QueueFull)
+        ));
+        assert_eq!(
+            ds.stats().unwrap(),
+            DiskSchedulerStats {
+                queue_depth: 4,
+                queue_capacity: 4,
+                in_flight: 1,
+            }
+        );
+
+        drop(held);
+        let v = Arc::new(Mutex::new(vec![0; 4096]));
+        ds.request(true, v, 20, call_tx).unwrap();
+        for _ in 0..6 {
+            call_rx.recv().unwrap().unwrap();
+        }
+        assert_eq!(ds.stats().unwrap().queue_depth, 0);
+    }
+
+    #[test]
+    fn test_disk_scheduler_submit() {
+        let dm = Arc::new(DiskManager::new(4096, None, true).unwrap());
+        let mut ds = DiskScheduler::new(dm);
+        ds.new_page(2).unwrap();
+
+        let v = Arc::new(Mutex::new(vec![7; 4096]));
+        let write = ds.submit(IoPriority::BackgroundFlush, true, v, 2).unwrap();
+        crate::io_future::block_on(write).unwrap();
+
+        let v = Arc::new(Mutex::new(vec![0; 4096]));
+        let read = ds
+            .submit(IoPriority::ForegroundRead, false, v.clone(), 2)
+            .unwrap();
+        crate::io_future::block_on(read).unwrap();
+        assert_eq!(*v.lock().unwrap(), vec![7; 4096]);
+
+        let v = Arc::new(Mutex::new(vec![0; 4096]));
+        let read = ds.submit(IoPriority::ForegroundRead, false, v, 3).unwrap();
+        assert!(matches!(
+            crate::io_future::block_on(read),
+            Err(Error::PageOutOfRange { page_id: 3 })
+        ));
+    }
+
+    #[test]
+    fn test_disk_scheduler_cancel() {
+        let dm = Arc::new(DiskManager::new(4096, None, true).unwrap());
+        let mut ds = DiskScheduler::with_workers(dm, 1);
+        ds.new_page(4).unwrap();
+
+        // Hold the only worker up so the rest stay queued
+        let (call_tx, call_rx) = mpsc::channel();
+        let blocker = Arc::new(Mutex::new(vec![0; 4096]));
+        let held = blocker.lock().unwrap();
+        let running = ds.request(true, blocker.clone(), 1, call_tx).unwrap();
+        while ds.stats().unwrap().in_flight == 0 {
+            std::thread::yield_now();
+        }
+
+        let (flush_tx, flush_rx) = mpsc::channel();
+        let v = Arc::new(Mutex::new(vec![9; 4096]));
+        let flush = ds
+            .request_with_priority(IoPriority::BackgroundFlush, true, v, 3, flush_tx)
+            .unwrap();
+        assert!(!running.cancel());
+        assert!(flush.cancel());
+        assert!(!flush.cancel());
+        assert!(matches!(flush_rx.recv().unwrap(), Err(Error::Cancelled)));
+
+        drop(held);
+        call_rx.recv().unwrap().unwrap();
+
+        // The cancelled write never reached the page
+        let (call_tx, call_rx) = mpsc::channel();
+        let v = Arc::new(Mutex::new(vec![0; 4096]));
+        ds.request(false, v.clone(), 3, call_tx).unwrap();
+        call_rx.recv().unwrap().unwrap();
+        assert_eq!(*v.lock().unwrap(), vec![0; 4096]);
+    }
 }
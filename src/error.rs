@@ -0,0 +1,185 @@
+use crate::clock_replacer::EvictionError;
+use std::fmt;
+use std::sync::{PoisonError, TryLockError};
+
+/// [Error] is returned by every fallible call in the crate, from the
+/// [crate::disk_manager::DiskManager] up through the
+/// [crate::buffer_pool_manager::BufferPoolManager]. It is also what
+/// the disk scheduler hands back on a request's callback.
+#[derive(Debug)]
+pub enum Error {
+    /// The page id is zero or past the end of the page file.
+    PageOutOfRange {
+        page_id: usize,
+    },
+    /// The page id is in range but isn't currently allocated.
+    PageNotAllocated {
+        page_id: usize,
+    },
+    /// The page is pinned and can't be deleted.
+    PagePinned {
+        page_id: usize,
+    },
+    /// Every frame in the buffer pool is in use.
+    NoFreeFrame,
+    /// The replacer can track fewer frames than the pool has.
+    ReplacerTooSmall {
+        capacity: usize,
+        num_frames: usize,
+    },
+    /// A sharded pool needs at least one instance and a frame for
+    /// every instance.
+    InvalidInstanceCount {
+        num_instances: usize,
+        num_frames: usize,
+    },
+    /// The page table needs at least one partition.
+    InvalidPartitionCount {
+        num_partitions: usize,
+    },
+    Io(std::io::Error),
+    /// A page, or the header when page_id is 0, failed its checksum.
+    Corruption {
+        page_id: usize,
+    },
+    /// A lock was held by another thread and waiting wasn't an option.
+    LockContention,
+    /// A thread panicked while holding a lock.
+    Poisoned,
+    /// The request was cancelled before a worker picked it up.
+    Cancelled,
+    /// The disk scheduler's queue is at capacity.
+    QueueFull,
+    /// The disk scheduler has been shut down or its workers are no
+    /// longer running.
+    Disconnected,
+    InvalidMagic(u64),
+    UnsupportedVersion(u32),
+    PageSizeMismatch {
+        expected: usize,
+        found: usize,
+    },
+    /// The page size leaves no room for the free page map next to the
+    /// superblock.
+    PageSizeTooSmall {
+        page_size: usize,
+        min: usize,
+    },
+    PageCountMismatch {
+        header: usize,
+        file: usize,
+    },
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::PageOutOfRange { page_id } => write!(f, "page {} is out of range", page_id),
+            Error::PageNotAllocated { page_id } => {
+                write!(f, "page {} is not allocated", page_id)
+            }
+            Error::PagePinned { page_id } => write!(f, "page {} is pinned", page_id),
+            Error::NoFreeFrame => write!(f, "no free frame in the buffer pool"),
+            Error::ReplacerTooSmall {
+                capacity,
+                num_frames,
+            } => write!(
+                f,
+                "replacer tracks {} frames but the pool has {}",
+                capacity, num_frames
+            ),
+            Error::InvalidInstanceCount {
+                num_instances,
+                num_frames,
+            } => write!(
+                f,
+                "can't split {} frames over {} instances",
+                num_frames, num_instances
+            ),
+            Error::InvalidPartitionCount { num_partitions } => {
+                write!(
+                    f,
+                    "can't split the page table in to {} partitions",
+                    num_partitions
+                )
+            }
+            Error::Io(err) => write!(f, "io error: {}", err),
+            Error::Corruption { page_id } => write!(f, "checksum mismatch on page {}", page_id),
+            Error::LockContention => write!(f, "lock is held by another thread"),
+            Error::Poisoned => write!(f, "lock was poisoned"),
+            Error::Cancelled => write!(f, "disk request was cancelled"),
+            Error::QueueFull => write!(f, "disk scheduler queue is full"),
+            Error::Disconnected => write!(f, "disk scheduler is disconnected"),
+            Error::InvalidMagic(magic) => {
+                write!(f, "invalid data file magic number: {:#x}", magic)
+            }
+            Error::UnsupportedVersion(version) => {
+                write!(f, "unsupported data file version: {}", version)
+            }
+            Error::PageSizeMismatch { expected, found } => write!(
+                f,
+                "page size mismatch: expected {} found {}",
+                expected, found
+            ),
+            Error::PageSizeTooSmall { page_size, min } => write!(
+                f,
+                "page size {} is too small, it must be at least {}",
+                page_size, min
+            ),
+            Error::PageCountMismatch { header, file } => write!(
+                f,
+                "page count mismatch: header has {} pages file has {}",
+                header, file
+            ),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(err: std::io::Error) -> Self {
+        Error::Io(err)
+    }
+}
+
+impl From<Error> for std::io::Error {
+    fn from(err: Error) -> Self {
+        match err {
+            Error::Io(err) => err,
+            err => std::io::Error::other(err),
+        }
+    }
+}
+
+impl From<EvictionError> for Error {
+    fn from(err: EvictionError) -> Self {
+        match err {
+            EvictionError::NoFreeBuffer => Error::NoFreeFrame,
+        }
+    }
+}
+
+impl<T> From<PoisonError<T>> for Error {
+    fn from(_: PoisonError<T>) -> Self {
+        Error::Poisoned
+    }
+}
+
+impl<T> From<TryLockError<T>> for Error {
+    fn from(err: TryLockError<T>) -> Self {
+        match err {
+            TryLockError::WouldBlock => Error::LockContention,
+            TryLockError::Poisoned(_) => Error::Poisoned,
+        }
+    }
+}
@@ -0,0 +1,147 @@
+use crate::error::{Error, Result};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+
+#[derive(Default)]
+struct Slot {
+    result: Option<Result<()>>,
+    waker: Option<Waker>,
+}
+
+/// [Completion] is how a worker reports the result of a disk request
+/// back to whoever submitted it, either over a channel or by resolving
+/// an [IoFuture].
+pub struct Completion(Option<Kind>);
+
+enum Kind {
+    Channel(Sender<Result<()>>),
+    Future(Arc<Mutex<Slot>>),
+}
+
+impl Completion {
+    /// future returns a completion along with the [IoFuture] it
+    /// resolves.
+    pub fn future() -> (Completion, IoFuture) {
+        let slot = Arc::new(Mutex::new(Slot::default()));
+        let future = IoFuture { slot: slot.clone() };
+        (Completion(Some(Kind::Future(slot))), future)
+    }
+
+    /// complete hands over the result. A requester that stopped
+    /// waiting on it is not an error.
+    pub fn complete(mut self, result: Result<()>) {
+        match self.0.take() {
+            Some(Kind::Channel(sender)) => {
+                let _ = sender.send(result);
+            }
+            Some(Kind::Future(slot)) => resolve(&slot, result),
+            None => {}
+        }
+    }
+}
+
+impl From<Sender<Result<()>>> for Completion {
+    fn from(sender: Sender<Result<()>>) -> Self {
+        Completion(Some(Kind::Channel(sender)))
+    }
+}
+
+impl Drop for Completion {
+    fn drop(&mut self) {
+        // Dropping a sender already tells a channel the request is
+        // gone, a future has to be told explicitly or it never wakes
+        if let Some(Kind::Future(slot)) = self.0.take() {
+            resolve(&slot, Err(Error::Disconnected));
+        }
+    }
+}
+
+fn resolve(slot: &Mutex<Slot>, result: Result<()>) {
+    let waker = match slot.lock() {
+        Ok(mut slot) => {
+            slot.result = Some(result);
+            slot.waker.take()
+        }
+        Err(_) => None,
+    };
+
+    if let Some(waker) = waker {
+        waker.wake();
+    }
+}
+
+/// [IoFuture] resolves to the result of a disk request once a worker
+/// has completed it. It needs no particular runtime, the worker wakes
+/// the task directly.
+pub struct IoFuture {
+    slot: Arc<Mutex<Slot>>,
+}
+
+impl Future for IoFuture {
+    type Output = Result<()>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut slot = self.slot.lock()?;
+        match slot.result.take() {
+            Some(result) => Poll::Ready(result),
+            None => {
+                slot.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+/// block_on drives a future to completion on the current thread,
+/// parking it between polls.
+#[cfg(test)]
+pub(crate) fn block_on<F: Future>(future: F) -> F::Output {
+    use std::task::Wake;
+
+    struct ThreadWaker(std::thread::Thread);
+
+    impl Wake for ThreadWaker {
+        fn wake(self: Arc<Self>) {
+            self.0.unpark();
+        }
+    }
+
+    let waker = Waker::from(Arc::new(ThreadWaker(std::thread::current())));
+    let mut cx = Context::from_waker(&waker);
+    let mut future = std::pin::pin!(future);
+    loop {
+        match future.as_mut().poll(&mut cx) {
+            Poll::Ready(output) => return output,
+            Poll::Pending => std::thread::park(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+
+    #[test]
+    fn test_io_future() {
+        let (completion, future) = Completion::future();
+        let worker = std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_millis(10));
+            completion.complete(Ok(()));
+        });
+        assert!(block_on(future).is_ok());
+        worker.join().unwrap();
+
+        // A request dropped without an answer still resolves
+        let (completion, future) = Completion::future();
+        drop(completion);
+        assert!(matches!(block_on(future), Err(Error::Disconnected)));
+
+        let (tx, rx) = mpsc::channel();
+        Completion::from(tx).complete(Err(Error::QueueFull));
+        assert!(matches!(rx.recv().unwrap(), Err(Error::QueueFull)));
+    }
+}
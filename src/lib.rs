@@ -1,7 +1,24 @@
+pub mod access_strategy;
+pub mod arc_replacer;
+pub mod atomic_clock_replacer;
+mod background_worker;
 pub mod buffer_pool_manager;
-pub(crate) mod clock_replacer;
-pub(crate) mod disk_manager;
-pub(crate) mod disk_scheduler;
+pub mod clock_replacer;
+mod crc32c;
+pub mod disk_manager;
+pub mod disk_scheduler;
+pub mod error;
+pub mod io_future;
+pub mod lru_k_replacer;
+pub mod lru_replacer;
+mod page_latch;
+pub mod replacement_policy;
+#[allow(dead_code)]
 mod replicator;
+mod request_queue;
+pub mod sharded_buffer_pool;
+pub mod two_q_replacer;
 
-const DEFAULT_PAGE_SIZE: usize = 4096;
+pub use error::{Error, Result};
+
+pub const DEFAULT_PAGE_SIZE: usize = 4096;
@@ -0,0 +1,106 @@
+use crate::clock_replacer::EvictionError;
+use crate::replacement_policy::ReplacementPolicy;
+use std::collections::{BTreeSet, HashMap};
+use std::sync::{Mutex, MutexGuard, PoisonError};
+
+/// [LruReplacer] evicts the evictable frame that was used least
+/// recently.
+pub struct LruReplacer {
+    state: Mutex<LruState>,
+}
+
+#[derive(Default)]
+struct LruState {
+    tick: u64,
+    frames: HashMap<usize, Entry>,
+    /// order holds the evictable frames by their last access
+    order: BTreeSet<(u64, usize)>,
+}
+
+struct Entry {
+    last_access: u64,
+    evictable: bool,
+}
+
+impl LruReplacer {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            state: Mutex::new(LruState {
+                frames: HashMap::with_capacity(capacity),
+                ..Default::default()
+            }),
+        }
+    }
+
+    fn state(&self) -> MutexGuard<'_, LruState> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl ReplacementPolicy for LruReplacer {
+    fn record_access(&self, frame_id: usize, _page_id: usize) {
+        let state = &mut *self.state();
+        state.tick += 1;
+
+        let entry = state.frames.entry(frame_id).or_insert(Entry {
+            last_access: 0,
+            evictable: true,
+        });
+        if entry.evictable {
+            state.order.remove(&(entry.last_access, frame_id));
+            state.order.insert((state.tick, frame_id));
+        }
+        entry.last_access = state.tick;
+    }
+
+    fn set_evictable(&self, frame_id: usize, evictable: bool) {
+        let state = &mut *self.state();
+        let Some(entry) = state.frames.get_mut(&frame_id) else {
+            return;
+        };
+
+        if evictable {
+            state.order.insert((entry.last_access, frame_id));
+        } else {
+            state.order.remove(&(entry.last_access, frame_id));
+        }
+        entry.evictable = evictable;
+    }
+
+    fn evict(&self) -> Result<usize, EvictionError> {
+        let mut state = self.state();
+        let (_, frame_id) = state.order.pop_first().ok_or(EvictionError::NoFreeBuffer)?;
+        state.frames.remove(&frame_id);
+        Ok(frame_id)
+    }
+
+    fn remove(&self, frame_id: usize) {
+        let mut state = self.state();
+        if let Some(entry) = state.frames.remove(&frame_id) {
+            state.order.remove(&(entry.last_access, frame_id));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lru_replacer() {
+        let replacer = LruReplacer::new(4);
+        for frame_id in 0..4 {
+            replacer.record_access(frame_id, frame_id + 1);
+        }
+        replacer.record_access(0, 1);
+        replacer.set_evictable(1, false);
+
+        assert_eq!(replacer.evict().unwrap(), 2);
+        replacer.remove(3);
+        assert_eq!(replacer.evict().unwrap(), 0);
+        assert!(matches!(replacer.evict(), Err(EvictionError::NoFreeBuffer)));
+
+        replacer.set_evictable(1, true);
+        assert_eq!(replacer.evict().unwrap(), 1);
+    }
+}
@@ -0,0 +1,197 @@
+use std::sync::{Condvar, Mutex, MutexGuard, PoisonError};
+
+/// [PageLatch] is the reader-writer latch a page guard holds for as
+/// long as it lives. Any number of readers can share it while a
+/// writer has it to itself. Waiting writers and upgrades hold back
+/// new readers so a stream of readers can't starve them.
+#[derive(Default)]
+pub(crate) struct PageLatch {
+    state: Mutex<LatchState>,
+    changed: Condvar,
+}
+
+#[derive(Default)]
+struct LatchState {
+    readers: usize,
+    writer: bool,
+    writers_waiting: usize,
+    /// upgrading is set while a reader waits for the others to leave
+    upgrading: bool,
+}
+
+impl LatchState {
+    fn can_read(&self) -> bool {
+        !self.writer && !self.upgrading && self.writers_waiting == 0
+    }
+
+    fn can_write(&self) -> bool {
+        !self.writer && !self.upgrading && self.readers == 0
+    }
+}
+
+impl PageLatch {
+    pub(crate) fn lock_shared(&self) {
+        let mut state = self.state();
+        while !state.can_read() {
+            state = self.wait(state);
+        }
+        state.readers += 1;
+    }
+
+    pub(crate) fn try_lock_shared(&self) -> bool {
+        let mut state = self.state();
+        if !state.can_read() {
+            return false;
+        }
+
+        state.readers += 1;
+        true
+    }
+
+    pub(crate) fn lock_exclusive(&self) {
+        let mut state = self.state();
+        state.writers_waiting += 1;
+        while !state.can_write() {
+            state = self.wait(state);
+        }
+        state.writers_waiting -= 1;
+        state.writer = true;
+    }
+
+    pub(crate) fn try_lock_exclusive(&self) -> bool {
+        let mut state = self.state();
+        if !state.can_write() {
+            return false;
+        }
+
+        state.writer = true;
+        true
+    }
+
+    pub(crate) fn unlock_shared(&self) {
+        self.state().readers -= 1;
+        self.changed.notify_all();
+    }
+
+    pub(crate) fn unlock_exclusive(&self) {
+        self.state().writer = false;
+        self.changed.notify_all();
+    }
+
+    /// upgrade turns a shared hold in to the exclusive one, waiting
+    /// for the other readers to leave. Only one reader can wait to
+    /// upgrade at a time, as two would wait on each other forever, so
+    /// it returns false straight away if another reader got there
+    /// first. The shared hold is kept either way.
+    pub(crate) fn upgrade(&self) -> bool {
+        let mut state = self.state();
+        if state.upgrading {
+            return false;
+        }
+
+        state.upgrading = true;
+        while state.readers > 1 {
+            state = self.wait(state);
+        }
+        state.upgrading = false;
+        state.readers = 0;
+        state.writer = true;
+        true
+    }
+
+    /// try_upgrade is upgrade without the wait, it only succeeds for
+    /// the sole reader.
+    pub(crate) fn try_upgrade(&self) -> bool {
+        let mut state = self.state();
+        if state.readers != 1 || state.upgrading {
+            return false;
+        }
+
+        state.readers = 0;
+        state.writer = true;
+        true
+    }
+
+    /// downgrade turns the exclusive hold in to a shared one without
+    /// letting another writer in between.
+    pub(crate) fn downgrade(&self) {
+        let mut state = self.state();
+        state.writer = false;
+        state.readers = 1;
+        drop(state);
+        self.changed.notify_all();
+    }
+
+    /// state hands out the latch state. Nothing can panic while it is
+    /// held, so a poisoned lock is still safe to use.
+    fn state(&self) -> MutexGuard<'_, LatchState> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn wait<'a>(&self, state: MutexGuard<'a, LatchState>) -> MutexGuard<'a, LatchState> {
+        self.changed
+            .wait(state)
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn test_page_latch() {
+        let latch = PageLatch::default();
+        latch.lock_shared();
+        assert!(latch.try_lock_shared());
+        assert!(!latch.try_lock_exclusive());
+        assert!(!latch.try_upgrade());
+
+        latch.unlock_shared();
+        assert!(latch.try_upgrade());
+        assert!(!latch.try_lock_shared());
+        latch.downgrade();
+        assert!(latch.try_lock_shared());
+        latch.unlock_shared();
+        latch.unlock_shared();
+
+        assert!(latch.try_lock_exclusive());
+        latch.unlock_exclusive();
+    }
+
+    #[test]
+    fn test_page_latch_upgrade_waits_for_readers() {
+        let latch = Arc::new(PageLatch::default());
+        latch.lock_shared();
+        latch.lock_shared();
+
+        let upgraded = Arc::new(AtomicBool::new(false));
+        let upgrader = {
+            let latch = latch.clone();
+            let upgraded = upgraded.clone();
+            thread::spawn(move || {
+                assert!(latch.upgrade());
+                upgraded.store(true, Ordering::SeqCst);
+                latch.unlock_exclusive();
+            })
+        };
+
+        // A second upgrade would deadlock with the first, and new
+        // readers wait behind it
+        while !latch.state().upgrading {
+            thread::sleep(Duration::from_millis(1));
+        }
+        assert!(!upgraded.load(Ordering::SeqCst));
+        assert!(!latch.upgrade());
+        assert!(!latch.try_lock_shared());
+
+        latch.unlock_shared();
+        upgrader.join().unwrap();
+        assert!(upgraded.load(Ordering::SeqCst));
+        assert!(latch.try_lock_exclusive());
+    }
+}
@@ -0,0 +1,96 @@
+use crate::arc_replacer::ArcReplacer;
+use crate::atomic_clock_replacer::AtomicClockReplacer;
+use crate::buffer_pool_manager::ReplacerNode;
+use crate::clock_replacer::{EvictionError, Replacer};
+use crate::lru_k_replacer::LruKReplacer;
+use crate::lru_replacer::LruReplacer;
+use crate::two_q_replacer::TwoQReplacer;
+
+/// [ReplacementPolicy] decides which frame the
+/// [crate::buffer_pool_manager::BufferPoolManager] gives up when it
+/// needs room for a page. Frame ids run from 0 up to the capacity the
+/// policy was built with. Policies lock internally so the pool can
+/// share one between threads.
+pub trait ReplacementPolicy: Send + Sync {
+    /// record_access notes that the frame, holding page_id, was used.
+    /// Frames are tracked from their first access and start out
+    /// evictable.
+    fn record_access(&self, frame_id: usize, page_id: usize);
+
+    /// set_evictable controls whether evict may choose the frame.
+    fn set_evictable(&self, frame_id: usize, evictable: bool);
+
+    /// evict chooses a victim among the evictable frames and stops
+    /// tracking it.
+    fn evict(&self) -> Result<usize, EvictionError>;
+
+    /// remove stops tracking the frame without counting it as an
+    /// eviction, for frames released back to the free list.
+    fn remove(&self, frame_id: usize);
+
+    /// ahead_of_hand lists the evictable frames in the next lookahead
+    /// steps of a clock hand, in the order the hand reaches them,
+    /// without moving the hand. Policies without a hand return None.
+    fn ahead_of_hand(&self, _lookahead: usize) -> Option<Vec<usize>> {
+        None
+    }
+
+    /// capacity is the number of frames a policy with fixed slots can
+    /// track. Policies that grow as needed return None.
+    fn capacity(&self) -> Option<usize> {
+        None
+    }
+}
+
+impl<P: ReplacementPolicy + ?Sized> ReplacementPolicy for Box<P> {
+    fn record_access(&self, frame_id: usize, page_id: usize) {
+        (**self).record_access(frame_id, page_id)
+    }
+
+    fn set_evictable(&self, frame_id: usize, evictable: bool) {
+        (**self).set_evictable(frame_id, evictable)
+    }
+
+    fn evict(&self) -> Result<usize, EvictionError> {
+        (**self).evict()
+    }
+
+    fn remove(&self, frame_id: usize) {
+        (**self).remove(frame_id)
+    }
+
+    fn ahead_of_hand(&self, lookahead: usize) -> Option<Vec<usize>> {
+        (**self).ahead_of_hand(lookahead)
+    }
+
+    fn capacity(&self) -> Option<usize> {
+        (**self).capacity()
+    }
+}
+
+/// [Policy] selects a [ReplacementPolicy] when a pool is built.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Policy {
+    Clock,
+    /// AtomicClock is the clock sweep without a lock, for pools hit
+    /// by many threads at once.
+    AtomicClock,
+    Lru,
+    /// LRU-K evicts by the age of the k-th most recent access.
+    LruK(usize),
+    TwoQ,
+    Arc,
+}
+
+impl Policy {
+    pub fn build(self, capacity: usize) -> Box<dyn ReplacementPolicy> {
+        match self {
+            Policy::Clock => Box::new(Replacer::<ReplacerNode>::new(capacity)),
+            Policy::AtomicClock => Box::new(AtomicClockReplacer::new(capacity)),
+            Policy::Lru => Box::new(LruReplacer::new(capacity)),
+            Policy::LruK(k) => Box::new(LruKReplacer::new(capacity, k)),
+            Policy::TwoQ => Box::new(TwoQReplacer::new(capacity)),
+            Policy::Arc => Box::new(ArcReplacer::new(capacity)),
+        }
+    }
+}
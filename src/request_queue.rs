@@ -0,0 +1,423 @@
+use crate::disk_manager::DiskManagerRequest;
+use crate::disk_scheduler::DiskSchedulerStats;
+use crate::error::{Error, Result};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::Instant;
+
+struct QueuedRequest {
+    id: u64,
+    request: DiskManagerRequest,
+    deadline: Instant,
+}
+
+struct QueueState {
+    /// requests are kept in submission order.
+    requests: VecDeque<QueuedRequest>,
+    /// in_flight holds the page ids currently owned by a worker.
+    in_flight: HashSet<usize>,
+    next_id: u64,
+    closed: bool,
+}
+
+impl QueueState {
+    /// next picks the request to run next. Requests past their
+    /// deadline go first, earliest deadline first. Otherwise the most
+    /// urgent priority class wins, oldest first within a class. A
+    /// request is only runnable once every earlier request for its
+    /// page has completed.
+    fn next(&self, now: Instant) -> Option<usize> {
+        let mut seen = HashSet::new();
+        self.requests
+            .iter()
+            .enumerate()
+            .filter(|(_, queued)| {
+                let page_id = queued.request.page_id();
+                seen.insert(page_id) && !self.in_flight.contains(&page_id)
+            })
+            .min_by_key(|(index, queued)| {
+                if queued.deadline <= now {
+                    (0, Some(queued.deadline), *index)
+                } else {
+                    (1 + queued.request.priority() as usize, None, *index)
+                }
+            })
+            .map(|(index, _)| index)
+    }
+
+    /// take_run removes the request at index along with up to max - 1
+    /// runnable requests in the same direction for the pages directly
+    /// around it. The run is returned in page order and every page in
+    /// it is marked in flight.
+    fn take_run(&mut self, index: usize, max: usize) -> Vec<DiskManagerRequest> {
+        let head = &self.requests[index].request;
+        let (page_id, is_write) = (head.page_id(), head.is_write());
+
+        let mut seen = HashSet::new();
+        let mut runnable = HashMap::new();
+        for (i, queued) in self.requests.iter().enumerate() {
+            let p_id = queued.request.page_id();
+            if seen.insert(p_id)
+                && !self.in_flight.contains(&p_id)
+                && queued.request.is_write() == is_write
+            {
+                runnable.insert(p_id, i);
+            }
+        }
+
+        // A run locks every buffer in it at once, so it ends at a
+        // request that shares a buffer with one already taken
+        let mut buffers = vec![head.data()];
+        let mut joins = |p_id: usize| match runnable.get(&p_id) {
+            Some(&i) => {
+                let data = self.requests[i].request.data();
+                let shared = buffers.iter().any(|buffer| Arc::ptr_eq(buffer, data));
+                buffers.push(data);
+                !shared
+            }
+            None => false,
+        };
+
+        let (mut first, mut last) = (page_id, page_id);
+        while last - first + 1 < max && joins(last + 1) {
+            last += 1;
+        }
+        while last - first + 1 < max && first > 0 && joins(first - 1) {
+            first -= 1;
+        }
+
+        // Remove from the back so the remaining indexes stay valid
+        let mut indexes = (first..=last)
+            .map(|p_id| runnable[&p_id])
+            .collect::<Vec<_>>();
+        indexes.sort_unstable_by(|a, b| b.cmp(a));
+        let mut run = indexes
+            .into_iter()
+            .filter_map(|i| self.requests.remove(i))
+            .map(|queued| queued.request)
+            .collect::<Vec<_>>();
+        run.sort_unstable_by_key(|request| request.page_id());
+
+        self.in_flight
+            .extend(run.iter().map(|request| request.page_id()));
+        run
+    }
+}
+
+/// [RequestQueue] is the submission queue between the
+/// [crate::disk_scheduler::DiskScheduler] and its workers. Workers
+/// block on a condvar while there is nothing for them to do and are
+/// woken as soon as a request is pushed.
+///
+/// The queue holds at most capacity requests waiting for a worker.
+/// Submitters either block until a worker makes room or are turned
+/// away with [Error::QueueFull].
+///
+/// Requests for the same page are handed out one at a time in the
+/// order they were submitted. Requests for other pages may overtake
+/// them while they wait.
+pub(crate) struct RequestQueue {
+    state: Mutex<QueueState>,
+    available: Condvar,
+    space: Condvar,
+    capacity: usize,
+}
+
+impl RequestQueue {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            state: Mutex::new(QueueState {
+                requests: VecDeque::new(),
+                in_flight: HashSet::new(),
+                next_id: 0,
+                closed: false,
+            }),
+            available: Condvar::new(),
+            space: Condvar::new(),
+            capacity: capacity.max(1),
+        }
+    }
+
+    /// push waits for room in the queue before adding the request.
+    /// It returns the id to cancel the request with.
+    pub fn push(&self, request: DiskManagerRequest) -> Result<u64> {
+        let mut state = self.state.lock()?;
+        while !state.closed && state.requests.len() >= self.capacity {
+            state = self.space.wait(state)?;
+        }
+
+        self.enqueue(&mut state, request)
+    }
+
+    /// try_push adds the request only if the queue has room for it.
+    pub fn try_push(&self, request: DiskManagerRequest) -> Result<u64> {
+        let mut state = self.state.lock()?;
+        if !state.closed && state.requests.len() >= self.capacity {
+            return Err(Error::QueueFull);
+        }
+
+        self.enqueue(&mut state, request)
+    }
+
+    fn enqueue(&self, state: &mut QueueState, request: DiskManagerRequest) -> Result<u64> {
+        if state.closed {
+            return Err(Error::Disconnected);
+        }
+
+        let deadline = Instant::now() + request.priority().deadline();
+        let id = state.next_id;
+        state.next_id += 1;
+        state.requests.push_back(QueuedRequest {
+            id,
+            request,
+            deadline,
+        });
+        self.available.notify_one();
+        Ok(id)
+    }
+
+    /// pop blocks until a request for a page no other worker is
+    /// working on is available. The caller must hand the page back
+    /// with [RequestQueue::complete]. Once the queue is closed the
+    /// remaining requests are still handed out, after which it
+    /// returns None.
+    #[cfg(test)]
+    pub fn pop(&self) -> Option<DiskManagerRequest> {
+        self.pop_run(1).pop()
+    }
+
+    /// pop_run is [RequestQueue::pop] for up to max requests covering
+    /// consecutive pages, all reads or all writes, so they can be
+    /// issued as one operation. Each page must be handed back with
+    /// [RequestQueue::complete]. An empty run means the queue is
+    /// closed and drained.
+    pub fn pop_run(&self, max: usize) -> Vec<DiskManagerRequest> {
+        let Ok(mut state) = self.state.lock() else {
+            return vec![];
+        };
+        loop {
+            if let Some(index) = state.next(Instant::now()) {
+                let run = state.take_run(index, max.max(1));
+                self.space.notify_all();
+                return run;
+            }
+            if state.closed && state.requests.is_empty() {
+                return vec![];
+            }
+
+            state = match self.available.wait(state) {
+                Ok(state) => state,
+                Err(_) => return vec![],
+            };
+        }
+    }
+
+    /// cancel removes a request that is still waiting for a worker.
+    /// Requests already handed out are left alone.
+    pub fn cancel(&self, id: u64) -> Option<DiskManagerRequest> {
+        let mut state = self.state.lock().ok()?;
+        let index = state.requests.iter().position(|queued| queued.id == id)?;
+        let queued = state.requests.remove(index)?;
+
+        // Requests for the same page queued behind it may now be runnable
+        self.available.notify_all();
+        self.space.notify_all();
+        Some(queued.request)
+    }
+
+    pub fn complete(&self, page_id: usize) {
+        if let Ok(mut state) = self.state.lock() {
+            state.in_flight.remove(&page_id);
+        }
+        // Queued requests for this page may now be runnable
+        self.available.notify_all();
+    }
+
+    pub fn close(&self) {
+        if let Ok(mut state) = self.state.lock() {
+            state.closed = true;
+        }
+        self.available.notify_all();
+        self.space.notify_all();
+    }
+
+    pub fn stats(&self) -> Result<DiskSchedulerStats> {
+        let state = self.state.lock()?;
+        Ok(DiskSchedulerStats {
+            queue_depth: state.requests.len(),
+            queue_capacity: self.capacity,
+            in_flight: state.in_flight.len(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::disk_manager::IoPriority;
+    use std::sync::{mpsc, Arc};
+
+    fn request(page_id: usize, priority: IoPriority) -> DiskManagerRequest {
+        rw_request(false, page_id, priority)
+    }
+
+    fn rw_request(is_write: bool, page_id: usize, priority: IoPriority) -> DiskManagerRequest {
+        let (tx, _) = mpsc::channel();
+        DiskManagerRequest::DiskRwRequest {
+            is_write,
+            data: Arc::new(Mutex::new(vec![])),
+            page_id,
+            priority,
+            callback: tx.into(),
+        }
+    }
+
+    fn pop_page(queue: &RequestQueue) -> usize {
+        let page_id = queue.pop().unwrap().page_id();
+        queue.complete(page_id);
+        page_id
+    }
+
+    #[test]
+    fn test_request_queue_priority() {
+        let queue = RequestQueue::new(16);
+        queue.push(request(1, IoPriority::Prefetch)).unwrap();
+        queue.push(request(2, IoPriority::BackgroundFlush)).unwrap();
+        queue.push(request(3, IoPriority::ForegroundWrite)).unwrap();
+        queue.push(request(4, IoPriority::ForegroundRead)).unwrap();
+        queue.push(request(5, IoPriority::ForegroundRead)).unwrap();
+
+        let order = (0..5).map(|_| pop_page(&queue)).collect::<Vec<_>>();
+        assert_eq!(order, vec![4, 5, 3, 2, 1]);
+    }
+
+    #[test]
+    fn test_request_queue_deadline() {
+        let queue = RequestQueue::new(16);
+        queue.push(request(1, IoPriority::Prefetch)).unwrap();
+        queue.push(request(2, IoPriority::BackgroundFlush)).unwrap();
+        std::thread::sleep(IoPriority::Prefetch.deadline());
+
+        // Both have expired, so they run ahead of fresh foreground
+        // reads in the order their deadlines passed
+        queue.push(request(3, IoPriority::ForegroundRead)).unwrap();
+        let order = (0..3).map(|_| pop_page(&queue)).collect::<Vec<_>>();
+        assert_eq!(order, vec![2, 1, 3]);
+    }
+
+    #[test]
+    fn test_request_queue_same_page_order() {
+        let queue = RequestQueue::new(16);
+        queue.push(request(1, IoPriority::BackgroundFlush)).unwrap();
+        queue.push(request(1, IoPriority::ForegroundRead)).unwrap();
+        queue.push(request(2, IoPriority::ForegroundWrite)).unwrap();
+
+        // The read of page 1 can't overtake the flush of page 1
+        let first = queue.pop().unwrap();
+        assert_eq!(first.page_id(), 2);
+        let second = queue.pop().unwrap();
+        assert_eq!(second.page_id(), 1);
+        assert_eq!(second.priority(), IoPriority::BackgroundFlush);
+
+        // Page 1 is in flight so nothing else is runnable yet
+        queue.complete(2);
+        assert!(queue.state.lock().unwrap().next(Instant::now()).is_none());
+
+        queue.complete(1);
+        assert_eq!(queue.pop().unwrap().priority(), IoPriority::ForegroundRead);
+    }
+
+    #[test]
+    fn test_request_queue_run() {
+        let queue = RequestQueue::new(16);
+        for page_id in [3, 1, 2, 6] {
+            queue
+                .push(rw_request(true, page_id, IoPriority::BackgroundFlush))
+                .unwrap();
+        }
+        queue
+            .push(rw_request(false, 4, IoPriority::BackgroundFlush))
+            .unwrap();
+        queue
+            .push(rw_request(true, 5, IoPriority::BackgroundFlush))
+            .unwrap();
+        queue
+            .push(rw_request(true, 2, IoPriority::ForegroundWrite))
+            .unwrap();
+
+        // The read of page 4 breaks the run of writes
+        let run = queue.pop_run(8);
+        let pages = run.iter().map(|r| r.page_id()).collect::<Vec<_>>();
+        assert_eq!(pages, vec![1, 2, 3]);
+
+        let run = queue.pop_run(8);
+        let pages = run.iter().map(|r| r.page_id()).collect::<Vec<_>>();
+        assert_eq!(pages, vec![5, 6]);
+        assert!(run.iter().all(|r| r.is_write()));
+
+        // The second write of page 2 waits for the first to complete
+        assert_eq!(queue.pop_run(8)[0].page_id(), 4);
+        for page_id in [1, 2, 3] {
+            queue.complete(page_id);
+        }
+        let run = queue.pop_run(2);
+        assert_eq!(run.len(), 1);
+        assert_eq!(run[0].priority(), IoPriority::ForegroundWrite);
+    }
+
+    #[test]
+    fn test_request_queue_capacity() {
+        let queue = Arc::new(RequestQueue::new(2));
+        queue.push(request(1, IoPriority::ForegroundRead)).unwrap();
+        queue.push(request(2, IoPriority::ForegroundRead)).unwrap();
+        assert!(matches!(
+            queue.try_push(request(3, IoPriority::ForegroundRead)),
+            Err(Error::QueueFull)
+        ));
+
+        let stats = queue.stats().unwrap();
+        assert_eq!(stats.queue_depth, 2);
+        assert_eq!(stats.queue_capacity, 2);
+        assert_eq!(stats.in_flight, 0);
+
+        // A blocked push goes through once a worker takes a request
+        let pusher = {
+            let queue = queue.clone();
+            std::thread::spawn(move || queue.push(request(3, IoPriority::ForegroundRead)))
+        };
+        std::thread::sleep(std::time::Duration::from_millis(20));
+        assert!(!pusher.is_finished());
+
+        assert_eq!(queue.pop().unwrap().page_id(), 1);
+        pusher.join().unwrap().unwrap();
+        let stats = queue.stats().unwrap();
+        assert_eq!(stats.queue_depth, 2);
+        assert_eq!(stats.in_flight, 1);
+
+        // Closing wakes blocked submitters and turns them away
+        let pusher = {
+            let queue = queue.clone();
+            std::thread::spawn(move || queue.push(request(4, IoPriority::ForegroundRead)))
+        };
+        queue.close();
+        assert!(matches!(pusher.join().unwrap(), Err(Error::Disconnected)));
+    }
+
+    #[test]
+    fn test_request_queue_cancel() {
+        let queue = RequestQueue::new(16);
+        let flush = queue.push(request(1, IoPriority::BackgroundFlush)).unwrap();
+        queue.push(request(1, IoPriority::ForegroundRead)).unwrap();
+        let prefetch = queue.push(request(2, IoPriority::Prefetch)).unwrap();
+
+        assert_eq!(queue.cancel(flush).unwrap().page_id(), 1);
+        assert!(queue.cancel(flush).is_none());
+
+        // The read of page 1 no longer waits behind the flush
+        let read = queue.pop().unwrap();
+        assert_eq!(read.priority(), IoPriority::ForegroundRead);
+        assert_eq!(queue.pop().unwrap().priority(), IoPriority::Prefetch);
+        assert!(queue.cancel(prefetch).is_none());
+        assert_eq!(queue.stats().unwrap().queue_depth, 0);
+    }
+}
@@ -0,0 +1,196 @@
+use crate::buffer_pool_manager::{BackgroundWriterConfig, BufferPoolManager, ReadPage, WritePage};
+use crate::disk_manager::DiskManager;
+use crate::disk_scheduler::DiskScheduler;
+use crate::error::{Error, Result};
+use crate::replacement_policy::Policy;
+use std::sync::Arc;
+
+/// [ShardedBufferPool] splits its frames between independent
+/// [BufferPoolManager] instances over one data file, routing each
+/// page to an instance by its id. Every instance has its own page
+/// table, free list, replacer and disk scheduler, so pages in
+/// different instances never share a lock, misses included. Within
+/// an instance hits only avoid each other with
+/// [Policy::AtomicClock], the other policies keep their state behind
+/// one lock.
+pub struct ShardedBufferPool {
+    instances: Vec<BufferPoolManager>,
+}
+
+impl ShardedBufferPool {
+    /// new spreads num_frames as evenly as it can over num_instances
+    /// pools, each with its own replacer built from policy. Every
+    /// instance gets at least one frame.
+    pub fn new(
+        disk_manager: Arc<DiskManager>,
+        policy: Policy,
+        page_size: usize,
+        num_frames: usize,
+        num_instances: usize,
+    ) -> Result<ShardedBufferPool> {
+        if num_instances == 0 || num_frames < num_instances {
+            return Err(Error::InvalidInstanceCount {
+                num_instances,
+                num_frames,
+            });
+        }
+
+        let instances = (0..num_instances)
+            .map(|i| {
+                let frames =
+                    num_frames / num_instances + usize::from(i < num_frames % num_instances);
+                let disk_scheduler = DiskScheduler::new(Arc::clone(&disk_manager));
+                BufferPoolManager::with_policy(disk_scheduler, policy, page_size, frames)
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(ShardedBufferPool { instances })
+    }
+
+    /// instance returns the pool that owns page_id.
+    pub fn instance(&self, page_id: usize) -> &BufferPoolManager {
+        &self.instances[page_id % self.instances.len()]
+    }
+
+    pub fn num_instances(&self) -> usize {
+        self.instances.len()
+    }
+
+    /// new_page allocates a page in the shared data file and makes it
+    /// addressable in every instance.
+    pub fn new_page(&self) -> Result<usize> {
+        let page_id = self.instances[0].new_page()?;
+        for instance in &self.instances[1..] {
+            instance.track_page(page_id);
+        }
+
+        Ok(page_id)
+    }
+
+    pub fn delete_page(&self, page_id: usize) -> Result<()> {
+        self.instance(page_id).delete_page(page_id)
+    }
+
+    pub fn read_page(&self, page_id: usize) -> Result<ReadPage> {
+        self.instance(page_id).read_page(page_id)
+    }
+
+    pub fn write_page(&self, page_id: usize) -> Result<WritePage> {
+        self.instance(page_id).write_page(page_id)
+    }
+
+    pub fn try_read_page(&self, page_id: usize) -> Result<ReadPage> {
+        self.instance(page_id).try_read_page(page_id)
+    }
+
+    pub fn try_write_page(&self, page_id: usize) -> Result<WritePage> {
+        self.instance(page_id).try_write_page(page_id)
+    }
+
+    pub async fn read_page_async(&self, page_id: usize) -> Result<ReadPage> {
+        self.instance(page_id).read_page_async(page_id).await
+    }
+
+    pub async fn write_page_async(&self, page_id: usize) -> Result<WritePage> {
+        self.instance(page_id).write_page_async(page_id).await
+    }
+
+    pub fn flush_page(&self, page_id: usize) -> Result<()> {
+        self.instance(page_id).flush_page(page_id)
+    }
+
+    /// flush_all flushes every instance, returning the first error.
+    pub fn flush_all(&self) -> Result<()> {
+        self.instances
+            .iter()
+            .map(BufferPoolManager::flush_all)
+            .fold(Ok(()), Result::and)
+    }
+
+    /// start_background_writer starts a background writer in every
+    /// instance, each pacing itself by config.
+    pub fn start_background_writer(&self, config: BackgroundWriterConfig) -> Result<()> {
+        self.instances
+            .iter()
+            .try_for_each(|instance| instance.start_background_writer(config))
+    }
+
+    /// shutdown shuts every instance down, returning the first error.
+    pub fn shutdown(&self) -> Result<()> {
+        self.instances
+            .iter()
+            .map(BufferPoolManager::shutdown)
+            .fold(Ok(()), Result::and)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::DEFAULT_PAGE_SIZE;
+    use std::io::{Read, Write};
+    use std::thread;
+
+    #[test]
+    fn test_sharded_buffer_pool() {
+        let disk_manager = Arc::new(DiskManager::default());
+        let pool =
+            ShardedBufferPool::new(disk_manager, Policy::AtomicClock, DEFAULT_PAGE_SIZE, 10, 4)
+                .unwrap();
+        assert_eq!(pool.num_instances(), 4);
+
+        for (num_frames, num_instances) in [(10, 0), (3, 4)] {
+            assert!(matches!(
+                ShardedBufferPool::new(
+                    Arc::new(DiskManager::default()),
+                    Policy::AtomicClock,
+                    DEFAULT_PAGE_SIZE,
+                    num_frames,
+                    num_instances,
+                ),
+                Err(Error::InvalidInstanceCount { .. })
+            ));
+        }
+
+        let pages = (0..32)
+            .map(|_| pool.new_page().unwrap())
+            .collect::<Vec<_>>();
+        for page_id in &pages {
+            let mut wp = pool.write_page(*page_id).unwrap();
+            wp.write_all(format!("page-{}", page_id).as_bytes())
+                .unwrap();
+        }
+
+        // Readers of pages in different instances run side by side,
+        // and every instance evicts from its own frames
+        let pool = Arc::new(pool);
+        pool.start_background_writer(BackgroundWriterConfig::default())
+            .unwrap();
+        let readers = (0..4)
+            .map(|shard| {
+                let pool = Arc::clone(&pool);
+                let pages = pages.clone();
+                thread::spawn(move || {
+                    for page_id in pages.iter().filter(|page_id| *page_id % 4 == shard) {
+                        let mut rp = pool.read_page(*page_id).unwrap();
+                        let mut buf = [0_u8; DEFAULT_PAGE_SIZE];
+                        rp.read_exact(&mut buf).unwrap();
+
+                        let expected = format!("page-{}", page_id);
+                        assert_eq!(&buf[..expected.len()], expected.as_bytes());
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        for reader in readers {
+            reader.join().unwrap();
+        }
+
+        // A shared pool can still hand out pages, reusing the deleted one
+        pool.delete_page(pages[5]).unwrap();
+        assert_eq!(pool.new_page().unwrap(), pages[5]);
+        pool.flush_page(pages[6]).unwrap();
+        pool.flush_all().unwrap();
+        pool.shutdown().unwrap();
+    }
+}
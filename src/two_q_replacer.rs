@@ -0,0 +1,171 @@
+use crate::clock_replacer::EvictionError;
+use crate::replacement_policy::ReplacementPolicy;
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Mutex, MutexGuard, PoisonError};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Queue {
+    /// A1in is a FIFO of pages seen once while resident.
+    A1in,
+    /// Am is an LRU of pages that came back after leaving A1in.
+    Am,
+}
+
+/// [TwoQReplacer] is the full 2Q policy from Johnson and Shasha. New
+/// pages enter the A1in FIFO and are remembered in the A1out ghost
+/// list after being evicted from it. Only a page that is read in
+/// again while remembered is promoted to the main Am LRU, so one off
+/// scans never displace the hot set.
+pub struct TwoQReplacer {
+    /// kin is the size A1in may grow to before it is evicted from first
+    kin: usize,
+    /// kout is the number of evicted page ids remembered in A1out
+    kout: usize,
+    state: Mutex<TwoQState>,
+}
+
+#[derive(Default)]
+struct TwoQState {
+    tick: u64,
+    frames: HashMap<usize, Entry>,
+    a1out: VecDeque<usize>,
+}
+
+struct Entry {
+    page_id: usize,
+    queue: Queue,
+    /// stamp is the insert time in A1in and the last access in Am
+    stamp: u64,
+    evictable: bool,
+}
+
+impl TwoQReplacer {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            kin: (capacity / 4).max(1),
+            kout: (capacity / 2).max(1),
+            state: Mutex::new(TwoQState {
+                frames: HashMap::with_capacity(capacity),
+                ..Default::default()
+            }),
+        }
+    }
+
+    fn state(&self) -> MutexGuard<'_, TwoQState> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl TwoQState {
+    fn oldest(&self, queue: Queue) -> Option<usize> {
+        self.frames
+            .iter()
+            .filter(|(_, entry)| entry.queue == queue && entry.evictable)
+            .min_by_key(|(_, entry)| entry.stamp)
+            .map(|(frame_id, _)| *frame_id)
+    }
+}
+
+impl ReplacementPolicy for TwoQReplacer {
+    fn record_access(&self, frame_id: usize, page_id: usize) {
+        let state = &mut *self.state();
+        state.tick += 1;
+
+        match state.frames.get_mut(&frame_id) {
+            Some(entry) if entry.queue == Queue::Am => entry.stamp = state.tick,
+            // Correlated references in A1in don't count
+            Some(_) => {}
+            None => {
+                let queue = match state.a1out.iter().position(|p_id| *p_id == page_id) {
+                    Some(index) => {
+                        state.a1out.remove(index);
+                        Queue::Am
+                    }
+                    None => Queue::A1in,
+                };
+                state.frames.insert(
+                    frame_id,
+                    Entry {
+                        page_id,
+                        queue,
+                        stamp: state.tick,
+                        evictable: true,
+                    },
+                );
+            }
+        }
+    }
+
+    fn set_evictable(&self, frame_id: usize, evictable: bool) {
+        if let Some(entry) = self.state().frames.get_mut(&frame_id) {
+            entry.evictable = evictable;
+        }
+    }
+
+    fn evict(&self) -> Result<usize, EvictionError> {
+        let mut state = self.state();
+        let a1in_len = state
+            .frames
+            .values()
+            .filter(|entry| entry.queue == Queue::A1in)
+            .count();
+        let order = if a1in_len > self.kin {
+            [Queue::A1in, Queue::Am]
+        } else {
+            [Queue::Am, Queue::A1in]
+        };
+
+        let frame_id = order
+            .into_iter()
+            .find_map(|queue| state.oldest(queue))
+            .ok_or(EvictionError::NoFreeBuffer)?;
+        let entry = state.frames.remove(&frame_id).unwrap();
+        if entry.queue == Queue::A1in {
+            state.a1out.push_back(entry.page_id);
+            if state.a1out.len() > self.kout {
+                state.a1out.pop_front();
+            }
+        }
+
+        Ok(frame_id)
+    }
+
+    fn remove(&self, frame_id: usize) {
+        self.state().frames.remove(&frame_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_two_q_replacer() {
+        // Room for one page in A1in and two in A1out
+        let replacer = TwoQReplacer::new(4);
+        for frame_id in 0..4 {
+            replacer.record_access(frame_id, frame_id + 10);
+        }
+
+        // Pages 10 and 11 are evicted from A1in and come straight back
+        for frame_id in 0..2 {
+            assert_eq!(replacer.evict().unwrap(), frame_id);
+            replacer.record_access(frame_id, frame_id + 10);
+            assert_eq!(replacer.state().frames[&frame_id].queue, Queue::Am);
+        }
+
+        // A scan of one off pages only churns through A1in
+        for page_id in 20..30 {
+            let victim = replacer.evict().unwrap();
+            assert!(victim >= 2);
+            replacer.record_access(victim, page_id);
+        }
+        assert_eq!(replacer.state().a1out.len(), 2);
+
+        replacer.set_evictable(2, false);
+        replacer.set_evictable(3, false);
+        assert_eq!(replacer.evict().unwrap(), 0);
+        assert_eq!(replacer.evict().unwrap(), 1);
+        assert!(matches!(replacer.evict(), Err(EvictionError::NoFreeBuffer)));
+    }
+}